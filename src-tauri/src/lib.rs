@@ -1,5 +1,9 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use utils::api::{get_exe_path, get_voices_list, start_tts, get_app_version};
+use utils::api::{cancel_tts, get_exe_path, get_voices_list, start_tts, start_tts_stream, get_app_version};
+use tauri::Manager;
+use utils::batch::{get_batch_history, resume_batches, start_batch};
+use utils::job_store::{JobStore, DATABASE_FILE};
+use utils::jobs::JobRegistry;
 pub mod utils;
 
 #[tauri::command]
@@ -14,10 +18,31 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_sql::Builder::default().build())
+        .manage(JobRegistry::default())
+        .setup(|app| {
+            // 批量任务保存在前端使用的同一个数据库中，启动时继续未完成的任务；
+            // 数据库无法打开时不影响其他功能，只是批量任务不再保存
+            let store = match app.path().app_config_dir() {
+                Ok(dir) => tauri::async_runtime::block_on(JobStore::open(&dir.join(DATABASE_FILE)))
+                    .map_err(|e| e.msg),
+                Err(e) => Err(e.to_string()),
+            };
+            let store = store.unwrap_or_else(|e| {
+                eprintln!("打开任务数据库失败，批量任务不会保存: {}", e);
+                JobStore::unavailable(e)
+            });
+            app.manage(store);
+            tauri::async_runtime::spawn(resume_batches(app.handle().clone()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             get_voices_list,
             start_tts,
+            start_tts_stream,
+            cancel_tts,
+            start_batch,
+            get_batch_history,
             get_exe_path,
             get_app_version
         ])
@@ -1,25 +1,45 @@
+use crate::utils::audio::{riff_data_offset, riff_payload, AudioWriter};
 use crate::utils::custom_result::CustomResult;
-use crate::utils::sub_marker::generate_srt;
-use crate::utils::tts::TTS;
+use crate::utils::events::{ProgressReporter, StreamEvent, StreamTarget};
+use crate::utils::jobs::{CancelToken, JobRegistry};
+use crate::utils::output_format::{OutputFormat, DEFAULT_OUTPUT_FORMAT};
+use crate::utils::script::{parse_script, ScriptLine, SpeakerVoice};
+use crate::utils::ssml::{validate_ssml, validate_voice, SsmlError};
+use crate::utils::sub_marker::{
+    generate_srt, merge_messages, validate_vtt_cue_settings, write_subtitle, AssStyle, CueTiming,
+    MergeConstraints, SubMaker, SubtitleFormat, SubtitleOptions, DEFAULT_PAUSE_MS,
+};
+use crate::utils::tts::{MAX_CHUNK_BYTES, TTS};
 use base64::{engine::general_purpose, Engine as _};
 use futures_util::{sink::SinkExt, StreamExt};
 use reqwest::get;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_json::{from_str, json};
-use std::fs::{self, File};
-use std::io::Write;
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 use std::process::Command;
+use tauri::ipc::Channel;
+use tauri::{AppHandle, State};
 use tokio_tungstenite::{
     connect_async,
     tungstenite::{client::IntoClientRequest, Message},
 };
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+// 无法根据字节数计算时长时，每段音频末尾静音的估计时长（单位：100纳秒）
+const CHUNK_PADDING_TICKS: u64 = 8_750_000;
+// 配音模式需要按时间放置音频，所选格式不是 PCM 时改用该格式
+const DUB_OUTPUT_FORMAT: &str = "raw-24khz-16bit-mono-pcm";
+// 配音模式下每条字幕最多合成的次数
+const DUB_MAX_ATTEMPTS: usize = 4;
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct TTSData {
     voice: String,
+    // 批量合成时由输入文件提供
+    #[serde(default)]
     text: String,
     pitch: i32,
     rate: i32,
@@ -29,6 +49,103 @@ pub struct TTSData {
     merge_by_number_number: i32,
     root_path: String,
     open_folders: bool,
+    // 输入类型：text 为纯文本，ssml 为用户自行编写的 SSML 文档，
+    // subtitle 为 SRT/WebVTT 字幕（按实际音频重新计时），dub 为按字幕时间配音，
+    // script 为多角色对话脚本
+    #[serde(default = "default_input_type")]
+    input_type: String,
+    // 音频输出格式，见 output_format::OUTPUT_FORMATS
+    #[serde(default = "default_output_format")]
+    output_format: String,
+    // 字幕格式：srt、vtt、ass 或 lrc
+    #[serde(default = "default_subtitle_format")]
+    subtitle_format: String,
+    // WebVTT 字幕的位置设置，为空时不添加
+    #[serde(default)]
+    vtt_cue_settings: String,
+    // ASS 字幕的样式
+    #[serde(default)]
+    ass_style: AssStyle,
+    // ASS 卡拉OK模式，逐词高亮
+    #[serde(default)]
+    ass_karaoke: bool,
+    // 增强型 LRC，逐词时间
+    #[serde(default)]
+    lrc_enhanced: bool,
+    // mergeByConstraints 分句时的限制条件
+    #[serde(default)]
+    merge_constraints: MergeConstraints,
+    // 长字幕拆成两行，line_width 为每行的最大显示宽度
+    #[serde(default)]
+    line_wrap: bool,
+    #[serde(default = "default_line_width")]
+    line_width: usize,
+    // 按停顿拆分字幕，可与其他分句方式叠加
+    #[serde(default)]
+    pause_split: bool,
+    #[serde(default = "default_pause_threshold_ms")]
+    pause_threshold_ms: u64,
+    // 填补字幕间隔、最短显示时间和最小间隔
+    #[serde(default)]
+    cue_timing: CueTiming,
+    // 字幕整体偏移（毫秒）和时间缩放比例，例如音频放在片头之后
+    #[serde(default)]
+    time_offset_ms: i64,
+    #[serde(default = "default_time_scale")]
+    time_scale: f64,
+    // 配音模式下音频放不进字幕时间时，语速最多提高到该值
+    #[serde(default = "default_dub_max_rate")]
+    dub_max_rate: i32,
+    // 对话脚本中角色名到发音人的映射
+    #[serde(default)]
+    speakers: HashMap<String, SpeakerVoice>,
+    // 任务 ID，用于取消任务，为空时自动生成
+    #[serde(default)]
+    job_id: String,
+}
+
+impl TTSData {
+    // 批量合成中的一项：使用共同的设置和文件中的文本，不打开文件夹
+    pub fn with_text(&self, text: String, job_id: String) -> TTSData {
+        TTSData {
+            text,
+            job_id,
+            open_folders: false,
+            ..self.clone()
+        }
+    }
+
+    pub fn root_path(&self) -> &str {
+        &self.root_path
+    }
+}
+
+fn default_input_type() -> String {
+    "text".to_string()
+}
+
+fn default_output_format() -> String {
+    DEFAULT_OUTPUT_FORMAT.to_string()
+}
+
+fn default_subtitle_format() -> String {
+    "srt".to_string()
+}
+
+fn default_line_width() -> usize {
+    42
+}
+
+fn default_pause_threshold_ms() -> u64 {
+    DEFAULT_PAUSE_MS
+}
+
+fn default_time_scale() -> f64 {
+    1.0
+}
+
+fn default_dub_max_rate() -> i32 {
+    50
 }
 
 #[tauri::command]
@@ -56,7 +173,52 @@ pub async fn get_voices_list() -> Result<CustomResult, CustomResult> {
 }
 
 #[tauri::command]
-pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
+pub async fn start_tts(
+    data: TTSData,
+    jobs: State<'_, JobRegistry>,
+    app_handle: AppHandle,
+) -> Result<CustomResult, CustomResult> {
+    run_tts(data, None, &jobs, None, app_handle, true).await
+}
+
+// 流式合成：每收到一段音频和词边界就通过 on_event 发送给前端，
+// 不必等整段合成结束才能播放；返回值中不再包含完整音频
+#[tauri::command]
+pub async fn start_tts_stream(
+    data: TTSData,
+    on_event: Channel<StreamEvent>,
+    jobs: State<'_, JobRegistry>,
+    app_handle: AppHandle,
+) -> Result<CustomResult, CustomResult> {
+    run_tts(data, Some(&on_event), &jobs, None, app_handle, false).await
+}
+
+// 取消正在进行的合成任务，原来的调用返回 code 为 499 的结果，已生成的文件会被删除
+#[tauri::command]
+pub async fn cancel_tts(
+    job_id: String,
+    jobs: State<'_, JobRegistry>,
+) -> Result<CustomResult, CustomResult> {
+    if jobs.cancel(&job_id) {
+        Ok(CustomResult::success(None, None))
+    } else {
+        Err(CustomResult::error(
+            Some(format!("任务不存在或已经结束：{}", job_id)),
+            None,
+        ))
+    }
+}
+
+// 合成一个任务。channel 不为空时流式发送音频；include_audio 为 false 时
+// 返回值中不包含 base64 编码的音频（流式和批量合成时不需要）
+pub async fn run_tts(
+    data: TTSData,
+    channel: Option<&Channel<StreamEvent>>,
+    jobs: &JobRegistry,
+    parent: Option<&CancelToken>,
+    app_handle: AppHandle,
+    include_audio: bool,
+) -> Result<CustomResult, CustomResult> {
     let voice = data.voice;
     let text = data.text;
     let pitch = data.pitch;
@@ -67,38 +229,412 @@ pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
     let merge_number = data.merge_by_number_number;
     let mut root_path = data.root_path;
     let open_folders = data.open_folders;
+    let input_type = data.input_type;
+    let dub_max_rate = data.dub_max_rate;
+    let speakers = data.speakers;
+    let job_id = data.job_id;
+    let mut output_format = OutputFormat::from_name(&data.output_format)?;
+    let subtitle_options = SubtitleOptions {
+        format: SubtitleFormat::from_name(&data.subtitle_format)?,
+        vtt_cue_settings: Some(data.vtt_cue_settings),
+        ass_style: data.ass_style,
+        ass_karaoke: data.ass_karaoke,
+        lrc_enhanced: data.lrc_enhanced,
+        merge_constraints: data.merge_constraints,
+        line_width: if data.line_wrap && data.line_width > 0 {
+            Some(data.line_width)
+        } else {
+            None
+        },
+        pause_threshold_ms: if data.pause_split || sub_marker_option == "mergeByPause" {
+            Some(data.pause_threshold_ms)
+        } else {
+            None
+        },
+        cue_timing: data.cue_timing,
+        time_offset_ms: data.time_offset_ms,
+        time_scale: data.time_scale,
+    };
+    if subtitle_options.format == SubtitleFormat::Ass {
+        subtitle_options.ass_style.validate()?;
+    }
+    if subtitle_options.format == SubtitleFormat::Vtt {
+        validate_vtt_cue_settings(subtitle_options.vtt_cue_settings.as_deref().unwrap_or(""))?;
+    }
+    if sub_marker_option == "mergeByConstraints" {
+        subtitle_options.merge_constraints.validate()?;
+    }
+    subtitle_options.cue_timing.validate()?;
+    if !subtitle_options.time_scale.is_finite() || subtitle_options.time_scale <= 0.0 {
+        return Err(CustomResult::error(
+            Some("时间缩放比例必须大于0".to_string()),
+            None,
+        ));
+    }
 
     // 检查参数
-    if voice.is_empty() || text.is_empty() || sub_marker_option.is_empty() {
+    if text.is_empty() || sub_marker_option.is_empty() {
         return Err(CustomResult::error(Some("参数错误".to_string()), None));
     }
 
-    // 格式化数据
-    let pitch_str = if pitch >= 0 {
-        format!("+{}", pitch)
+    let tts_client = TTS {};
+    let send_request_id = Uuid::new_v4().to_string().replace('-', "");
+    let job_id = if job_id.is_empty() {
+        send_request_id.clone()
     } else {
-        format!("{}", pitch)
+        job_id
     };
+    let job = jobs.register_with_parent(&job_id, parent)?;
+    let cancel = job.token();
+
+    // 导入的 SRT/WebVTT 字幕，每条字幕单独合成，之后按实际音频重新计时
+    let mut imported: Option<SubMaker> = None;
+    // 对话脚本中每段音频对应的角色和台词
+    let mut script_lines: Option<Vec<ScriptLine>> = None;
+
+    // 生成每次请求要发送的 SSML 文档，以及用于字幕分句的纯文本
+    let (documents, all_text) = match input_type.as_str() {
+        "text" => {
+            if voice.is_empty() {
+                return Err(CustomResult::error(Some("参数错误".to_string()), None));
+            }
+            validate_voice(&voice)?;
 
-    let rate_str = if rate >= 0 {
-        format!("+{}", rate)
-    } else {
-        format!("{}", rate)
+            // 长文本拆分成多段，逐段合成后拼接
+            let chunks = tts_client.split_text_by_byte_length(&text, MAX_CHUNK_BYTES);
+            if chunks.is_empty() {
+                return Err(CustomResult::error(Some("参数错误".to_string()), None));
+            }
+            let documents = chunks
+                .iter()
+                .map(|chunk| tts_client.convert_to_ssml_text(&voice, chunk, pitch, rate, volume))
+                .collect::<Result<Vec<String>, CustomResult>>()?;
+            (documents, text.clone())
+        }
+        "ssml" => {
+            // 用户编写的 SSML 原样发送，不做拆分，超过单次请求的大小时由用户自行拆分
+            if text.len() > MAX_CHUNK_BYTES {
+                return Err(SsmlError::DocumentTooLarge(text.len(), MAX_CHUNK_BYTES).into());
+            }
+            let spoken_text = validate_ssml(&text)?;
+            (vec![text.clone()], spoken_text)
+        }
+        "script" => {
+            let mut lines: Vec<ScriptLine> = vec![];
+            let mut documents: Vec<String> = vec![];
+            for line in parse_script(&text, &speakers)? {
+                let speaker = &speakers[&line.speaker];
+                // 较长的台词同样拆分成多段
+                for chunk in tts_client.split_text_by_byte_length(&line.text, MAX_CHUNK_BYTES) {
+                    documents.push(tts_client.convert_to_ssml_text(
+                        &speaker.voice,
+                        &chunk,
+                        speaker.pitch.unwrap_or(pitch),
+                        speaker.rate.unwrap_or(rate),
+                        speaker.volume.unwrap_or(volume),
+                    )?);
+                    lines.push(ScriptLine {
+                        speaker: line.speaker.clone(),
+                        text: chunk,
+                    });
+                }
+            }
+            let all_text = lines
+                .iter()
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            script_lines = Some(lines);
+            (documents, all_text)
+        }
+        "subtitle" | "dub" => {
+            if voice.is_empty() {
+                return Err(CustomResult::error(Some("参数错误".to_string()), None));
+            }
+            validate_voice(&voice)?;
+
+            let maker = SubMaker::from_subtitle(&text)?;
+            let cue_texts = maker.cue_texts();
+            let documents = cue_texts
+                .iter()
+                .map(|cue| tts_client.convert_to_ssml_text(&voice, cue, pitch, rate, volume))
+                .collect::<Result<Vec<String>, CustomResult>>()?;
+            imported = Some(maker);
+            (documents, cue_texts.join("\n"))
+        }
+        _ => {
+            return Err(CustomResult::error(
+                Some(format!("不支持的输入类型：{}", input_type)),
+                None,
+            ))
+        }
     };
 
-    let volumn_str = if volume >= 0 {
-        format!("+{}", volume)
+    if input_type == "dub" {
+        if !(-100..=100).contains(&dub_max_rate) {
+            return Err(CustomResult::error(
+                Some("语速上限必须在-100到100之间".to_string()),
+                None,
+            ));
+        }
+        if !output_format.is_pcm() {
+            output_format = OutputFormat::from_name(DUB_OUTPUT_FORMAT)?;
+        }
+        // 配音需要先确定每条字幕的语速和位置，无法边合成边播放
+        if channel.is_some() {
+            return Err(CustomResult::error(
+                Some("配音模式不支持流式播放".to_string()),
+                None,
+            ));
+        }
+    }
+
+    if documents.len() > 1 && !output_format.supports_concatenation() {
+        return Err(CustomResult::error(
+            Some(format!(
+                "{} 格式不支持长文本分段合成，请缩短文本或更换音频格式",
+                output_format.name
+            )),
+            None,
+        ));
+    }
+
+    if root_path.is_empty() {
+        root_path = ".".to_string();
+    }
+
+    let path_str = format!("{}/{}", root_path, send_request_id);
+    let folder_path = Path::new(&path_str);
+    if !folder_path.exists() {
+        fs::create_dir_all(folder_path)
+            .map_err(|e| CustomResult::error(Some(format!("创建文件夹失败：{}", e)), None))?;
+    }
+
+    // 每段音频合成后立即写入文件
+    let output_path = format!(
+        "{}/output_{}.{}",
+        path_str,
+        send_request_id,
+        output_format.extension()
+    );
+    let mut audio_writer = AudioWriter::create(&output_path, output_format)
+        .map_err(|e| CustomResult::error(Some(format!("创建音频文件失败：{}", e)), None))?;
+    if let Some(channel) = channel {
+        let _ = channel.send(StreamEvent::Started {
+            job_id: job_id.clone(),
+            format: output_format.name,
+            mime: output_format.stream_mime_type(),
+            sample_rate: output_format.sample_rate,
+            bits_per_sample: output_format.bits_per_sample,
+            channels: output_format.channels,
+        });
+    }
+    // 进度事件：第几段、已朗读的字数和已生成的音频时长
+    let mut progress = ProgressReporter::new(app_handle, &job_id, documents.len(), &all_text);
+    let mut messages: Vec<Value> = vec![];
+    let mut offset_compensation: u64 = 0;
+    // 每段音频中语音的开始和结束时间
+    let mut chunk_times: Vec<(u64, u64)> = vec![];
+    // 每段音频的字幕数据在 messages 中的范围
+    let mut chunk_ranges: Vec<std::ops::Range<usize>> = vec![];
+
+    // 配音模式中放不进字幕时间的字幕
+    let mut dub_report = Value::Null;
+    let synthesis = async {
+        if let (Some(maker), "dub") = (imported.as_ref(), input_type.as_str()) {
+            let dubbing = DubSettings {
+                voice: &voice,
+                pitch,
+                rate,
+                volume,
+                max_rate: dub_max_rate,
+            };
+            let (times, dub_messages, report) = dub_cues(
+                &tts_client,
+                cancel,
+                &mut progress,
+                &mut audio_writer,
+                output_format,
+                &dubbing,
+                maker,
+            )
+            .await?;
+            chunk_times = times;
+            messages = dub_messages;
+            dub_report = json!(report);
+        } else {
+            for (index, document) in documents.iter().enumerate() {
+                cancel.check()?;
+                progress.start_chunk(index, offset_compensation);
+                let stream = channel.map(|channel| StreamTarget {
+                    channel,
+                    offset: offset_compensation,
+                });
+                let (chunk_audio, mut chunk_messages) = synthesize_chunk(
+                    &tts_client,
+                    document,
+                    output_format,
+                    stream.as_ref(),
+                    cancel,
+                    &mut progress,
+                )
+                .await?;
+
+                // 当前段的字幕时间需要加上之前所有音频的时长
+                let chunk_duration = output_format
+                    .duration_ticks(&chunk_audio)
+                    .unwrap_or_else(|| last_boundary_end(&chunk_messages) + CHUNK_PADDING_TICKS);
+                let speech_end = match last_boundary_end(&chunk_messages) {
+                    0 => chunk_duration,
+                    end => end,
+                };
+                chunk_times.push((offset_compensation, offset_compensation + speech_end));
+                shift_metadata_offset(&mut chunk_messages, offset_compensation);
+                offset_compensation += chunk_duration;
+                progress.finish_chunk(offset_compensation);
+
+                audio_writer.write_chunk(&chunk_audio).map_err(|e| {
+                    CustomResult::error(Some(format!("写入音频文件失败：{}", e)), None)
+                })?;
+                chunk_ranges.push(messages.len()..messages.len() + chunk_messages.len());
+                messages.extend(chunk_messages);
+            }
+        }
+        Ok::<(), CustomResult>(())
+    }
+    .await;
+    // 合成失败或取消时删除已生成的部分文件
+    let finished = synthesis.and_then(|_| {
+        audio_writer
+            .finish()
+            .map_err(|e| CustomResult::error(Some(format!("写入音频文件失败：{}", e)), None))
+    });
+    if let Err(e) = finished {
+        let _ = fs::remove_dir_all(&path_str);
+        return Err(e);
+    }
+    if let Some(channel) = channel {
+        let _ = channel.send(StreamEvent::Finished);
+    }
+
+    // 写入JSON数据（仅用于测试）
+    // let json_path = format!("{}/output_{}.json", path_str, send_request_id);
+    // let mut json_file = File::create(&json_path)
+    //     .map_err(|e| CustomResult::error(Some(format!("创建JSON文件失败：{}", e)), None))?;
+    // let json_str = serde_json::to_string(&messages)
+    //     .map_err(|e| CustomResult::error(Some(format!("序列化JSON失败：{}", e)), None))?;
+    // json_file
+    //     .write_all(json_str.as_bytes())
+    //     .map_err(|e| CustomResult::error(Some(format!("写入JSON文件失败：{}", e)), None))?;
+
+    // 写入文本数据（仅用于测试）
+    // let text_path = format!("{}/output_{}.txt", path_str, send_request_id);
+    // let mut text_file = File::create(&text_path)
+    //     .map_err(|e| CustomResult::error(Some(format!("创建文本文件失败：{}", e)), None))?;
+    // let text = text.replace("\n", "\r\n");
+    // text_file
+    //     .write_all(text.as_bytes())
+    //     .map_err(|e| CustomResult::error(Some(format!("写入文本文件失败：{}", e)), None))?;
+
+    // 字幕生成的附加信息（如无法与原文对齐的片段），字幕失败不影响音频
+    let mut subtitle_info = Value::Null;
+    let subtitle_path = format!(
+        "{}/output_{}.{}",
+        path_str,
+        send_request_id,
+        subtitle_options.format.extension()
+    );
+    if sub_marker_switch {
+        let result = match (imported.as_mut(), script_lines.as_ref()) {
+            (_, Some(lines)) => script_subtitle(
+                &messages,
+                &chunk_ranges,
+                lines,
+                &subtitle_path,
+                &sub_marker_option,
+                merge_number,
+                &subtitle_options,
+            ),
+            (Some(maker), _) => retime_imported_subtitle(
+                maker,
+                &chunk_times,
+                &messages,
+                &subtitle_path,
+                &subtitle_options,
+            ),
+            (None, None) => generate_srt(
+                &messages,
+                &subtitle_path,
+                &sub_marker_option,
+                merge_number,
+                Some(&all_text),
+                &subtitle_options,
+            ),
+        };
+        match result {
+            Ok(result) => subtitle_info = result.data,
+            // 字幕失败时音频依然返回，错误信息交给前端提示
+            Err(e) => subtitle_info = json!({ "error": e.msg }),
+        }
+    }
+
+    if open_folders {
+        // 打开文件夹
+        let final_path = if cfg!(target_os = "windows") {
+            path_str.replace("/", "\\")
+        } else {
+            path_str
+        };
+        Command::new("explorer")
+            .arg(Path::new(final_path.as_str()))
+            .status()
+            .map_err(|e| {
+                CustomResult::error(
+                    Some(format!(
+                        "打开文件夹失败：{}<br>请手动前往文件夹查看：{}",
+                        e, final_path
+                    )),
+                    None,
+                )
+            })?;
+    }
+
+    // 编码成base64，流式合成时前端已经收到全部音频
+    let base64_audio = if include_audio {
+        json!(encode_audio_to_base64(&output_path)?)
     } else {
-        format!("{}", volume)
+        Value::Null
     };
 
-    let tts_client = TTS {};
+    Ok(CustomResult::success(
+        None,
+        Some(json!({
+            "audio": base64_audio,
+            "mime": output_format.mime_type(),
+            "format": output_format.name,
+            "job_id": job_id,
+            "output_path": output_path,
+            "subtitle_path": sub_marker_switch.then_some(&subtitle_path),
+            "subtitle": subtitle_info,
+            "dub": dub_report
+        })),
+    ))
+}
+
+// 建立一次websocket连接，合成一个 SSML 文档，返回音频数据和元数据
+async fn synthesize_chunk(
+    tts_client: &TTS,
+    ssml: &str,
+    output_format: &OutputFormat,
+    stream: Option<&StreamTarget<'_>>,
+    cancel: &CancelToken,
+    progress: &mut ProgressReporter,
+) -> Result<(Vec<u8>, Vec<Value>), CustomResult> {
     let send_request_id = Uuid::new_v4().to_string().replace('-', "");
     let sec_ms_gec_value = tts_client.generate_sec_ms_gec()?.data["hax"].clone();
     let sec_ms_gec = sec_ms_gec_value
         .as_str()
         .ok_or_else(|| CustomResult::error(Some("生成令牌字符串失败".to_string()), None))?;
-    let audio_output_format = "audio-24khz-48kbitrate-mono-mp3";
     let binary_delim = "Path:audio\r\n";
 
     let url_str = format!(
@@ -137,21 +673,14 @@ pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
         .map_err(|e| CustomResult::error(Some(format!("连接wss失败：{}", e.to_string())), None))?;
 
     // 发送音频格式设定
-    let audio_config = tts_client.convert_to_audio_format_websocket_string(audio_output_format);
+    let audio_config = tts_client.convert_to_audio_format_websocket_string(output_format.name);
     socket
         .send(Message::Text(audio_config.into()))
         .await
         .map_err(|e| CustomResult::error(Some(format!("发送音频格式失败：{}", e)), None))?;
 
     // 发送 SSML 文本
-    let ssml = tts_client.convert_to_ssml_websocket_string(
-        &send_request_id,
-        &voice,
-        &text,
-        &pitch_str,
-        &rate_str,
-        &volumn_str,
-    );
+    let ssml = tts_client.convert_to_ssml_websocket_string(&send_request_id, ssml);
     socket
         .send(Message::Text(ssml.into()))
         .await
@@ -160,8 +689,22 @@ pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
     // 接收数据
     let mut audio_data: Vec<u8> = Vec::new();
     let mut messages: Vec<Value> = vec![];
-
-    while let Some(msg) = socket.next().await {
+    // 已发送给前端的字节数，RIFF 格式每段都带有文件头，只发送其后的 PCM 数据
+    let mut streamed = 0;
+
+    loop {
+        // 等待下一条消息的同时响应取消，取消时关闭连接
+        let msg = tokio::select! {
+            msg = socket.next() => msg,
+            _ = cancel.cancelled() => {
+                let _ = socket.close(None).await;
+                return Err(CustomResult::cancelled(Some("任务已取消".to_string()), None));
+            }
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None => break,
+        };
         let msg =
             msg.map_err(|e| CustomResult::error(Some(format!("读取WS输出失败：{}", e)), None))?;
 
@@ -175,7 +718,11 @@ pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
                         let json_start = start_index + 2; // 跳过两个换行符
                         let json_part = &txt[json_start..];
                         // 尝试解析 JSON
-                        if let Ok(json) = serde_json::from_str(json_part) {
+                        if let Ok(json) = serde_json::from_str::<Value>(json_part) {
+                            if let Some(stream) = stream {
+                                send_word_boundaries(stream, &json);
+                            }
+                            progress.feed(&json);
                             messages.push(json);
                         } else {
                             eprintln!("JSON 解析失败: {}", json_part);
@@ -188,90 +735,248 @@ pub async fn start_tts(data: TTSData) -> Result<CustomResult, CustomResult> {
                     .windows(binary_delim.len())
                     .position(|w| w == binary_delim.as_bytes())
                 {
-                    audio_data.extend_from_slice(&bin[index + binary_delim.len()..]);
+                    let chunk = &bin[index + binary_delim.len()..];
+                    audio_data.extend_from_slice(chunk);
+                    if let (Some(stream), Some(start)) = (stream, riff_data_offset(&audio_data)) {
+                        let from = streamed.max(start);
+                        if from < audio_data.len() {
+                            stream.send(StreamEvent::Audio {
+                                chunk: general_purpose::STANDARD.encode(&audio_data[from..]),
+                            });
+                            streamed = audio_data.len();
+                        }
+                    }
                 }
             }
             _ => {}
         }
     }
 
-    if root_path.is_empty() {
-        root_path = ".".to_string();
-    }
+    let _ = socket.close(None).await;
 
-    let path_str = format!("{}/{}", root_path, send_request_id);
-    let folder_path = Path::new(&path_str);
-    if !folder_path.exists() {
-        fs::create_dir_all(folder_path)
-            .map_err(|e| CustomResult::error(Some(format!("创建文件夹失败：{}", e)), None))?;
-    }
+    Ok((audio_data, messages))
+}
 
-    // 保存音频数据
-    let output_path = format!("{}/output_{}.mp3", path_str, send_request_id);
-    let mut file = File::create(&output_path)
-        .map_err(|e| CustomResult::error(Some(format!("创建音频文件失败：{}", e)), None))?;
-    file.write_all(&audio_data)
-        .map_err(|e| CustomResult::error(Some(format!("写入音频文件失败：{}", e)), None))?;
+// 配音时每条字幕使用的发音人和参数
+struct DubSettings<'a> {
+    voice: &'a str,
+    pitch: i32,
+    rate: i32,
+    volume: i32,
+    max_rate: i32,
+}
 
-    // 写入JSON数据（仅用于测试）
-    // let json_path = format!("{}/output_{}.json", path_str, send_request_id);
-    // let mut json_file = File::create(&json_path)
-    //     .map_err(|e| CustomResult::error(Some(format!("创建JSON文件失败：{}", e)), None))?;
-    // let json_str = serde_json::to_string(&messages)
-    //     .map_err(|e| CustomResult::error(Some(format!("序列化JSON失败：{}", e)), None))?;
-    // json_file
-    //     .write_all(json_str.as_bytes())
-    //     .map_err(|e| CustomResult::error(Some(format!("写入JSON文件失败：{}", e)), None))?;
+// 配音模式：每条字幕的音频放在字幕的开始时间，前面用静音补齐。音频比字幕的时长
+// （到下一条字幕开始）更长时提高语速重新合成，直到放得下或达到语速上限；仍然放不下时
+// 后面的字幕顺延（顺延后没有剩余时间的字幕按原本的时长加速），并在报告中记录。返回每条字幕的新时间、字幕数据和报告
+async fn dub_cues(
+    tts_client: &TTS,
+    cancel: &CancelToken,
+    progress: &mut ProgressReporter,
+    audio_writer: &mut AudioWriter,
+    output_format: &OutputFormat,
+    settings: &DubSettings<'_>,
+    maker: &SubMaker,
+) -> Result<(Vec<(u64, u64)>, Vec<Value>, Vec<Value>), CustomResult> {
+    let write_error =
+        |e: std::io::Error| CustomResult::error(Some(format!("写入音频文件失败：{}", e)), None);
+    let texts = maker.cue_texts();
+    let times = maker.cue_times();
+    let mut cue_times: Vec<(u64, u64)> = vec![];
+    let mut messages: Vec<Value> = vec![];
+    let mut report: Vec<Value> = vec![];
+    // 已写入音频的时长
+    let mut cursor: u64 = 0;
+
+    for (i, (text, (cue_start, cue_end))) in texts.iter().zip(&times).enumerate() {
+        // 前一条字幕溢出时顺延，可用时长到下一条字幕开始为止，最后一条不限
+        let start = (*cue_start).max(cursor);
+        let slot = times
+            .get(i + 1)
+            .map(|(next_start, _)| next_start.saturating_sub(start));
+        // 加速的目标时长：之前的溢出已经占满这条字幕的时间时，按字幕原本的时长加速，
+        // 尽量缩短后面字幕的顺延
+        let target = slot.map(|slot| match slot {
+            0 => cue_end.saturating_sub(*cue_start),
+            slot => slot,
+        });
+        let mut rate = settings.rate;
+        let mut attempts = 0;
+
+        let (pcm, mut chunk_messages, speech_end) = loop {
+            cancel.check()?;
+            progress.start_chunk(i, start);
+            attempts += 1;
+            let ssml = tts_client.convert_to_ssml_text(
+                settings.voice,
+                text,
+                settings.pitch,
+                rate,
+                settings.volume,
+            )?;
+            let (audio, chunk_messages) =
+                synthesize_chunk(tts_client, &ssml, output_format, None, cancel, progress).await?;
+            let pcm = riff_payload(&audio).to_vec();
+            let duration = output_format.duration_ticks(&pcm).unwrap_or(0);
+            let speech_end = match last_boundary_end(&chunk_messages) {
+                0 => duration,
+                end => end,
+            };
+
+            let target = match target {
+                Some(target) if speech_end > target && target > 0 => target,
+                _ => break (pcm, chunk_messages, speech_end),
+            };
+            if rate >= settings.max_rate || attempts >= DUB_MAX_ATTEMPTS {
+                break (pcm, chunk_messages, speech_end);
+            }
+            // 语速与时长近似成反比，按比例估算需要的语速并留一点余量
+            let needed =
+                ((100 + rate) as f64 * speech_end as f64 / target as f64).ceil() as i32 - 100;
+            rate = (needed + 2).max(rate + 5).min(settings.max_rate);
+        };
 
-    // 写入文本数据（仅用于测试）
-    // let text_path = format!("{}/output_{}.txt", path_str, send_request_id);
-    // let mut text_file = File::create(&text_path)
-    //     .map_err(|e| CustomResult::error(Some(format!("创建文本文件失败：{}", e)), None))?;
-    // let text = text.replace("\n", "\r\n");
-    // text_file
-    //     .write_all(text.as_bytes())
-    //     .map_err(|e| CustomResult::error(Some(format!("写入文本文件失败：{}", e)), None))?;
+        let silence = output_format.pcm_bytes(start - cursor).unwrap_or(0);
+        audio_writer
+            .write_chunk(&vec![0u8; silence])
+            .map_err(write_error)?;
+
+        // 放得下时去掉超出字幕时长的结尾静音
+        let overflow = slot.map_or(0, |slot| speech_end.saturating_sub(slot));
+        let pcm = match slot {
+            Some(slot) if overflow == 0 => {
+                let limit = output_format.pcm_bytes(slot).unwrap_or(pcm.len());
+                &pcm[..pcm.len().min(limit)]
+            }
+            _ => &pcm[..],
+        };
+        audio_writer.write_chunk(pcm).map_err(write_error)?;
+        cursor = start + output_format.duration_ticks(pcm).unwrap_or(0);
+        progress.finish_chunk(cursor);
+
+        if overflow > 0 || start > *cue_start {
+            report.push(json!({
+                "index": i + 1,
+                "text": text,
+                "rate": rate,
+                "delay_ms": (start - cue_start) / 10_000,
+                "overflow_ms": overflow / 10_000,
+            }));
+        }
 
-    if sub_marker_switch {
-        let _ = generate_srt(
-            &messages,
-            &format!("{}/output_{}.srt", path_str, send_request_id),
-            &sub_marker_option,
-            merge_number,
-            Some(&text),
-        );
+        shift_metadata_offset(&mut chunk_messages, start);
+        messages.extend(chunk_messages);
+        cue_times.push((start, (*cue_end).max(start + speech_end)));
     }
 
-    if open_folders {
-        // 打开文件夹
-        let final_path = if cfg!(target_os = "windows") {
-            path_str.replace("/", "\\")
-        } else {
-            path_str
-        };
-        Command::new("explorer")
-            .arg(Path::new(final_path.as_str()))
-            .status()
-            .map_err(|e| {
-                CustomResult::error(
-                    Some(format!(
-                        "打开文件夹失败：{}<br>请手动前往文件夹查看：{}",
-                        e, final_path
-                    )),
-                    None,
-                )
-            })?;
-    }
+    Ok((cue_times, messages, report))
+}
 
-    // 编码成base64
-    let base64_audio = encode_audio_to_base64(&output_path)?;
+// 对话脚本的每段台词单独分句，字幕前加上角色名
+fn script_subtitle(
+    messages: &[Value],
+    chunk_ranges: &[std::ops::Range<usize>],
+    lines: &[ScriptLine],
+    subtitle_path: &str,
+    option: &str,
+    number: i32,
+    options: &SubtitleOptions,
+) -> Result<CustomResult, CustomResult> {
+    let mut maker = SubMaker::new();
+    let mut unaligned: Vec<Value> = vec![];
+
+    for (line, range) in lines.iter().zip(chunk_ranges) {
+        let (part, result) = merge_messages(
+            &messages[range.clone()],
+            option,
+            number,
+            Some(&line.text),
+            options,
+        )?;
+        if let Some(items) = result.data["unaligned"].as_array() {
+            unaligned.extend(items.iter().cloned());
+        }
+        maker.append(part, Some(&line.speaker));
+    }
 
+    write_subtitle(&mut maker, subtitle_path, options)?;
     Ok(CustomResult::success(
         None,
-        Some(json!({"audio": base64_audio})),
+        Some(json!({ "unaligned": unaligned })),
     ))
 }
 
+// 导入的字幕保持原有分句，只按实际合成的音频重新计时
+fn retime_imported_subtitle(
+    maker: &mut SubMaker,
+    chunk_times: &[(u64, u64)],
+    messages: &[Value],
+    subtitle_path: &str,
+    options: &SubtitleOptions,
+) -> Result<CustomResult, CustomResult> {
+    let _ = maker.set_cue_times(chunk_times)?;
+    maker.load_words(messages);
+    write_subtitle(maker, subtitle_path, options)?;
+    Ok(CustomResult::success(None, None))
+}
+
+// 把一条 audio.metadata 中的词边界转换成事件，时间加上之前音频的时长
+fn send_word_boundaries(stream: &StreamTarget<'_>, message: &Value) {
+    let metadata = match message.get("Metadata").and_then(|v| v.as_array()) {
+        Some(metadata) => metadata,
+        None => return,
+    };
+    for meta in metadata
+        .iter()
+        .filter(|meta| meta["Type"] == "WordBoundary")
+    {
+        let data = &meta["Data"];
+        if let (Some(offset), Some(duration), Some(text)) = (
+            data["Offset"].as_u64(),
+            data["Duration"].as_u64(),
+            data["text"]["Text"].as_str(),
+        ) {
+            stream.send(StreamEvent::WordBoundary {
+                offset_ms: (offset + stream.offset) / 10_000,
+                duration_ms: duration / 10_000,
+                text: text.to_string(),
+            });
+        }
+    }
+}
+
+// 最后一个词的结束时间（单位：100纳秒，与 WordBoundary 的 Offset 一致）
+fn last_boundary_end(messages: &[Value]) -> u64 {
+    messages
+        .iter()
+        .filter_map(|msg| msg.get("Metadata").and_then(|v| v.as_array()))
+        .flatten()
+        .filter_map(|meta| {
+            let offset = meta["Data"]["Offset"].as_u64()?;
+            let duration = meta["Data"]["Duration"].as_u64().unwrap_or(0);
+            Some(offset + duration)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// 给元数据中的所有 Offset 加上偏移量
+fn shift_metadata_offset(messages: &mut [Value], offset: u64) {
+    if offset == 0 {
+        return;
+    }
+
+    for msg in messages.iter_mut() {
+        if let Some(metadata) = msg.get_mut("Metadata").and_then(|v| v.as_array_mut()) {
+            for meta in metadata.iter_mut() {
+                if let Some(value) = meta["Data"]["Offset"].as_u64() {
+                    meta["Data"]["Offset"] = json!(value + offset);
+                }
+            }
+        }
+    }
+}
+
 #[tauri::command]
 pub async fn get_exe_path() -> Result<CustomResult, CustomResult> {
     let exe_path = std::env::current_exe()
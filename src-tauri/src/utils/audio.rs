@@ -0,0 +1,180 @@
+//! 音频文件写入，PCM 数据会被包装成 RIFF/WAVE 文件
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::output_format::{Container, OutputFormat};
+
+const WAV_HEADER_LEN: u32 = 44;
+
+// 生成标准的 44 字节 PCM WAV 文件头
+pub fn wav_header(
+    sample_rate: u32,
+    bits_per_sample: u16,
+    channels: u16,
+    data_len: u32,
+) -> [u8; 44] {
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+
+    let mut header = [0u8; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(data_len + WAV_HEADER_LEN - 8).to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    header[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&byte_rate.to_le_bytes());
+    header[32..34].copy_from_slice(&block_align.to_le_bytes());
+    header[34..36].copy_from_slice(&bits_per_sample.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+// 跳过 RIFF 文件头，返回 data 块中的 PCM 数据；不是 RIFF 数据时原样返回
+pub fn riff_payload(bytes: &[u8]) -> &[u8] {
+    &bytes[riff_data_offset(bytes).unwrap_or(bytes.len())..]
+}
+
+// data 块中 PCM 数据的起始位置，不是 RIFF 数据时为 0，文件头还没有接收完整时为 None
+pub fn riff_data_offset(bytes: &[u8]) -> Option<usize> {
+    if !bytes.starts_with(&b"RIFF"[..bytes.len().min(4)]) {
+        return Some(0);
+    }
+    if bytes.len() < 12 {
+        return None;
+    }
+    if &bytes[8..12] != b"WAVE" {
+        return Some(0);
+    }
+
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = u32::from_le_bytes([
+            bytes[pos + 4],
+            bytes[pos + 5],
+            bytes[pos + 6],
+            bytes[pos + 7],
+        ]) as usize;
+        pos += 8;
+        if id == b"data" {
+            return Some(pos);
+        }
+        // 块大小为奇数时有一个填充字节
+        pos += size + (size & 1);
+    }
+
+    None
+}
+
+// 边接收边写入的 WAV 文件，结束时回填文件头中的长度
+pub struct WavWriter<W: Write + Seek> {
+    inner: W,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    channels: u16,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut inner: W,
+        sample_rate: u32,
+        bits_per_sample: u16,
+        channels: u16,
+    ) -> io::Result<Self> {
+        // 先写入长度为 0 的文件头占位
+        inner.write_all(&wav_header(sample_rate, bits_per_sample, channels, 0))?;
+        Ok(WavWriter {
+            inner,
+            sample_rate,
+            bits_per_sample,
+            channels,
+            data_len: 0,
+        })
+    }
+
+    pub fn write_pcm(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(data.len())
+            .ok()
+            .and_then(|len| self.data_len.checked_add(len))
+            .filter(|len| *len <= u32::MAX - WAV_HEADER_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "WAV文件超过4GB"))?;
+        self.inner.write_all(data)?;
+        self.data_len = len;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let header = wav_header(
+            self.sample_rate,
+            self.bits_per_sample,
+            self.channels,
+            self.data_len,
+        );
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&header)?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+// 按输出格式写入音频文件，PCM 格式统一写成 WAV
+pub enum AudioWriter {
+    Plain(File),
+    Wav(WavWriter<File>),
+}
+
+impl AudioWriter {
+    pub fn create(path: &str, format: &OutputFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        match format.container {
+            Container::RawPcm | Container::Riff => Ok(AudioWriter::Wav(WavWriter::new(
+                file,
+                format.sample_rate,
+                format.bits_per_sample,
+                format.channels,
+            )?)),
+            _ => Ok(AudioWriter::Plain(file)),
+        }
+    }
+
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            AudioWriter::Plain(file) => file.write_all(chunk),
+            // 服务返回的 RIFF 数据每段都带有文件头，只取其中的 PCM 数据
+            AudioWriter::Wav(writer) => writer.write_pcm(riff_payload(chunk)),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            AudioWriter::Plain(mut file) => file.flush(),
+            AudioWriter::Wav(writer) => writer.finish().map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn riff_data_offset_waits_for_complete_header() {
+        let mut document = wav_header(24000, 16, 1, 4).to_vec();
+        document.extend_from_slice(&[1, 2, 3, 4]);
+
+        assert_eq!(riff_data_offset(&document[..2]), None);
+        assert_eq!(riff_data_offset(&document[..40]), None);
+        assert_eq!(riff_data_offset(&document), Some(44));
+        assert_eq!(riff_payload(&document), &[1, 2, 3, 4]);
+        // 不带文件头的 PCM 数据原样返回
+        assert_eq!(riff_data_offset(&[1, 2, 3, 4]), Some(0));
+        assert_eq!(riff_payload(&[1, 2, 3, 4]), &[1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,476 @@
+//! 批量合成：多个文本文件共用一套设置，按队列并发合成。
+//! 任务和每一项的状态保存在数据库中，应用重启后继续未完成的项
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use super::api::{run_tts, TTSData};
+use super::custom_result::CustomResult;
+use super::events::{BatchItemEvent, BATCH_EVENT};
+use super::job_store::{JobStore, PendingItem};
+use super::jobs::{CancelToken, JobRegistry};
+use chrono::Local;
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+use uuid::Uuid;
+
+const MAX_CONCURRENCY: usize = 8;
+const MAX_RETRIES: u32 = 5;
+// 重试前的等待时间，每次重试递增
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+
+#[derive(Deserialize)]
+pub struct BatchData {
+    // 要合成的文件
+    #[serde(default)]
+    files: Vec<String>,
+    // 或者合成文件夹中与 pattern 匹配的文件（支持 * 和 ?，不含子文件夹）
+    #[serde(default)]
+    folder: String,
+    #[serde(default = "default_pattern")]
+    pattern: String,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+    #[serde(default = "default_retries")]
+    retries: u32,
+    // 批量任务 ID，可用 cancel_tts 取消整个批量任务，为空时自动生成
+    #[serde(default)]
+    batch_id: String,
+    // 所有文件共用的合成设置，text 不需要填写
+    settings: TTSData,
+}
+
+fn default_pattern() -> String {
+    "*.txt".to_string()
+}
+
+fn default_concurrency() -> usize {
+    2
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+// 一项的合成结果，保存到数据库
+struct BatchItem {
+    status: &'static str,
+    attempts: u32,
+    error: Option<String>,
+    result: Value,
+}
+
+#[tauri::command]
+pub async fn start_batch(
+    data: BatchData,
+    jobs: State<'_, JobRegistry>,
+    store: State<'_, JobStore>,
+    app_handle: AppHandle,
+) -> Result<CustomResult, CustomResult> {
+    if data.concurrency == 0 || data.concurrency > MAX_CONCURRENCY {
+        return Err(CustomResult::error(
+            Some(format!("并发数必须在1到{}之间", MAX_CONCURRENCY)),
+            None,
+        ));
+    }
+    if data.retries > MAX_RETRIES {
+        return Err(CustomResult::error(
+            Some(format!("重试次数不能超过{}", MAX_RETRIES)),
+            None,
+        ));
+    }
+
+    let files = collect_files(&data.files, &data.folder, &data.pattern)?;
+    let batch_id = if data.batch_id.is_empty() {
+        Uuid::new_v4().to_string().replace('-', "")
+    } else {
+        data.batch_id.clone()
+    };
+    let batch = jobs.register(&batch_id)?;
+    if store.is_available() {
+        store
+            .create_batch(
+                &batch_id,
+                &data.settings,
+                data.concurrency,
+                data.retries,
+                &files,
+            )
+            .await?;
+    }
+
+    let context = BatchContext {
+        batch_id: &batch_id,
+        settings: &data.settings,
+        retries: data.retries,
+        jobs: &jobs,
+        store: &store,
+        cancel: batch.token(),
+        app_handle: &app_handle,
+    };
+    let items = files
+        .into_iter()
+        .enumerate()
+        .map(|(index, file)| PendingItem {
+            index,
+            file,
+            attempts: 0,
+        })
+        .collect();
+    context.run(items, data.concurrency).await
+}
+
+// 应用启动时继续上次没有完成的批量任务，已完成的项不再合成
+pub async fn resume_batches(app_handle: AppHandle) {
+    let jobs = app_handle.state::<JobRegistry>();
+    let store = app_handle.state::<JobStore>();
+    if !store.is_available() {
+        return;
+    }
+    let batches = match store.unfinished_batches().await {
+        Ok(batches) => batches,
+        Err(e) => {
+            eprintln!("读取未完成的批量任务失败: {}", e.msg);
+            return;
+        }
+    };
+
+    for pending in batches {
+        let batch = match jobs.register(&pending.batch_id) {
+            Ok(batch) => batch,
+            Err(_) => continue,
+        };
+        let context = BatchContext {
+            batch_id: &pending.batch_id,
+            settings: &pending.settings,
+            retries: pending.retries,
+            jobs: &jobs,
+            store: &store,
+            cancel: batch.token(),
+            app_handle: &app_handle,
+        };
+        if let Err(e) = context.run(pending.items, pending.concurrency).await {
+            eprintln!("继续批量任务 {} 失败: {}", pending.batch_id, e.msg);
+        }
+    }
+}
+
+// 最近的批量任务及每一项的状态、输出文件和错误
+#[tauri::command]
+pub async fn get_batch_history(
+    limit: Option<u32>,
+    store: State<'_, JobStore>,
+) -> Result<CustomResult, CustomResult> {
+    let history = store
+        .history(limit.unwrap_or(DEFAULT_HISTORY_LIMIT))
+        .await?;
+    Ok(CustomResult::success(None, Some(json!(history))))
+}
+
+struct BatchContext<'a> {
+    batch_id: &'a str,
+    settings: &'a TTSData,
+    retries: u32,
+    jobs: &'a JobRegistry,
+    store: &'a JobStore,
+    cancel: &'a CancelToken,
+    app_handle: &'a AppHandle,
+}
+
+impl BatchContext<'_> {
+    // 合成 items 中的各项，全部结束后写入汇总报告
+    async fn run(
+        &self,
+        items: Vec<PendingItem>,
+        concurrency: usize,
+    ) -> Result<CustomResult, CustomResult> {
+        for item in items.iter() {
+            self.emit(item.index, &item.file, "queued", item.attempts, None);
+        }
+
+        // 最多同时合成 concurrency 项
+        let tasks: Vec<_> = items.iter().map(|item| self.process(item)).collect();
+        let results: Vec<Value> = stream::iter(tasks).buffered(concurrency).collect().await;
+
+        // 重启后继续的任务也包含之前已完成的项，因此优先从数据库读取
+        let items = if self.store.is_available() {
+            match self.store.batch_items(self.batch_id).await {
+                Ok(items) => items,
+                Err(e) => {
+                    eprintln!("读取批量任务状态失败: {}", e.msg);
+                    results
+                }
+            }
+        } else {
+            results
+        };
+        let report = summary_report(self.batch_id, &items);
+        let root_path = match self.settings.root_path() {
+            "" => ".",
+            root_path => root_path,
+        };
+        let status = if self.cancel.is_cancelled() {
+            "cancelled"
+        } else {
+            "finished"
+        };
+        let report_path = write_report(root_path, self.batch_id, &report);
+        self.finish(status, report_path.as_deref().ok()).await;
+
+        let mut summary = report;
+        summary["report_path"] = json!(report_path?);
+        Ok(CustomResult::success(None, Some(summary)))
+    }
+
+    // 合成一个文件，失败时按设置重试；批量任务取消时同时取消正在合成的项
+    // 重启前已经合成过的次数计入重试次数
+    async fn process(&self, pending: &PendingItem) -> Value {
+        let (index, file) = (pending.index, pending.file.as_str());
+        let mut item = BatchItem {
+            status: "failed",
+            attempts: pending.attempts,
+            error: None,
+            result: Value::Null,
+        };
+
+        let text = match read_text(file) {
+            Ok(text) => text,
+            Err(e) => {
+                item.error = Some(e.msg);
+                return self.update(index, file, &item).await;
+            }
+        };
+
+        while item.attempts <= self.retries {
+            if self.cancel.is_cancelled() {
+                item.status = "cancelled";
+                break;
+            }
+            if item.attempts > 0 {
+                self.emit(index, file, "retrying", item.attempts, item.error.clone());
+                tokio::time::sleep(RETRY_DELAY * item.attempts).await;
+            }
+            item.attempts += 1;
+            self.emit(index, file, "running", item.attempts, None);
+            self.save(index, "running", item.attempts, None, &Value::Null)
+                .await;
+
+            let job_id = format!("{}-{}-{}", self.batch_id, index + 1, item.attempts);
+            let data = self.settings.with_text(text.clone(), job_id);
+            // 子任务随批量任务一起取消，包括注册之前批量任务就已经取消的情况
+            let result = run_tts(
+                data,
+                None,
+                self.jobs,
+                Some(self.cancel),
+                self.app_handle.clone(),
+                false,
+            )
+            .await;
+
+            match result {
+                Ok(result) => {
+                    item.status = "done";
+                    item.error = None;
+                    item.result = result.data;
+                    break;
+                }
+                Err(e) if e.is_cancelled() => {
+                    item.status = "cancelled";
+                    item.error = None;
+                    break;
+                }
+                Err(e) => item.error = Some(e.msg),
+            }
+        }
+
+        if item.status == "failed" && item.error.is_none() {
+            item.error = Some("应用重启前已用完重试次数".to_string());
+        }
+        self.update(index, file, &item).await
+    }
+
+    // 一项结束时通知前端并保存结果，返回写入汇总报告的内容
+    async fn update(&self, index: usize, file: &str, item: &BatchItem) -> Value {
+        self.emit(index, file, item.status, item.attempts, item.error.clone());
+        self.save(
+            index,
+            item.status,
+            item.attempts,
+            item.error.as_deref(),
+            &item.result,
+        )
+        .await;
+        json!({
+            "index": index,
+            "file": file,
+            "status": item.status,
+            "attempts": item.attempts,
+            "error": item.error,
+            "output_path": item.result["output_path"].as_str(),
+            "subtitle_path": item.result["subtitle_path"].as_str(),
+        })
+    }
+
+    // 保存失败不影响合成，只是重启后可能重复合成这一项
+    async fn save(
+        &self,
+        index: usize,
+        status: &str,
+        attempts: u32,
+        error: Option<&str>,
+        result: &Value,
+    ) {
+        if !self.store.is_available() {
+            return;
+        }
+        if let Err(e) = self
+            .store
+            .update_item(self.batch_id, index, status, attempts, error, result)
+            .await
+        {
+            eprintln!("保存批量任务状态失败: {}", e.msg);
+        }
+    }
+
+    async fn finish(&self, status: &str, report_path: Option<&str>) {
+        if !self.store.is_available() {
+            return;
+        }
+        if let Err(e) = self
+            .store
+            .finish_batch(self.batch_id, status, report_path)
+            .await
+        {
+            eprintln!("保存批量任务状态失败: {}", e.msg);
+        }
+    }
+
+    fn emit(
+        &self,
+        index: usize,
+        file: &str,
+        status: &'static str,
+        attempts: u32,
+        error: Option<String>,
+    ) {
+        let _ = self.app_handle.emit(
+            BATCH_EVENT,
+            BatchItemEvent {
+                batch_id: self.batch_id.to_string(),
+                index,
+                file: file.to_string(),
+                status,
+                attempts,
+                error,
+            },
+        );
+    }
+}
+
+// 汇总每一项的状态、输出文件和错误
+fn summary_report(batch_id: &str, items: &[Value]) -> Value {
+    let count = |status: &str| items.iter().filter(|item| item["status"] == status).count();
+    json!({
+        "batch_id": batch_id,
+        "finished_at": Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        "total": items.len(),
+        "done": count("done"),
+        "failed": count("failed"),
+        "cancelled": count("cancelled"),
+        "items": items,
+    })
+}
+
+fn write_report(root_path: &str, batch_id: &str, report: &Value) -> Result<String, CustomResult> {
+    fs::create_dir_all(root_path)
+        .map_err(|e| CustomResult::error(Some(format!("创建文件夹失败：{}", e)), None))?;
+    let report_path = format!("{}/batch_{}.json", root_path, batch_id);
+    let report_json = serde_json::to_string_pretty(report)
+        .map_err(|e| CustomResult::error(Some(format!("序列化JSON失败：{}", e)), None))?;
+    fs::write(&report_path, report_json)
+        .map_err(|e| CustomResult::error(Some(format!("写入汇总报告失败：{}", e)), None))?;
+    Ok(report_path)
+}
+
+// 合并 files 和 folder 中匹配的文件，去掉重复项
+fn collect_files(
+    files: &[String],
+    folder: &str,
+    pattern: &str,
+) -> Result<Vec<String>, CustomResult> {
+    let mut result: Vec<String> = files
+        .iter()
+        .map(|file| file.trim().to_string())
+        .filter(|file| !file.is_empty())
+        .collect();
+
+    if !folder.is_empty() {
+        let entries = fs::read_dir(folder)
+            .map_err(|e| CustomResult::error(Some(format!("读取文件夹失败：{}", e)), None))?;
+        let mut matched: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter(|entry| matches_pattern(&entry.file_name().to_string_lossy(), pattern))
+            .map(|entry| entry.path().to_string_lossy().to_string())
+            .collect();
+        matched.sort();
+        result.extend(matched);
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    result.retain(|file| seen.insert(file.clone()));
+
+    if result.is_empty() {
+        return Err(CustomResult::error(
+            Some("没有找到要合成的文件".to_string()),
+            None,
+        ));
+    }
+
+    Ok(result)
+}
+
+// 文件名通配符匹配，* 匹配任意多个字符，? 匹配一个字符，不区分大小写
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let (mut n, mut p) = (0, 0);
+    // 最近一个 * 的位置，以及它匹配到的名称位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                n += 1;
+                p += 1;
+            }
+            _ => match star {
+                // 回到上一个 *，让它多匹配一个字符
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn read_text(file: &str) -> Result<String, CustomResult> {
+    let path = Path::new(file);
+    let text = fs::read_to_string(path)
+        .map_err(|e| CustomResult::error(Some(format!("读取文件失败：{}：{}", file, e)), None))?;
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
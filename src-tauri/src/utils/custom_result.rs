@@ -1,6 +1,8 @@
 use serde_json::{json, Value};
 
-#[derive(serde::Serialize)]
+pub const CANCELLED_CODE: i32 = 499;
+
+#[derive(Debug, serde::Serialize)]
 pub struct CustomResult {
     pub code: i32,
     pub msg: String,
@@ -28,6 +30,19 @@ impl CustomResult {
         )
     }
 
+    // 任务被用户取消，与普通错误区分
+    pub fn cancelled(msg: Option<String>, data: Option<Value>) -> Self {
+        Self::new(
+            CANCELLED_CODE,
+            msg.unwrap_or("cancelled".to_string()),
+            data.unwrap_or(json!(null)),
+        )
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.code == CANCELLED_CODE
+    }
+
     pub fn to_string(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
@@ -0,0 +1,162 @@
+//! 合成过程中发送给前端的事件
+
+use serde::Serialize;
+use serde_json::Value;
+use tauri::ipc::Channel;
+use tauri::{AppHandle, Emitter};
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum StreamEvent {
+    // 开始合成，前端据此准备播放器。PCM 格式的音频不带文件头，需按采样率、位深和声道数播放
+    Started {
+        job_id: String,
+        format: &'static str,
+        mime: &'static str,
+        sample_rate: u32,
+        bits_per_sample: u16,
+        channels: u16,
+    },
+    // 收到的一段音频数据（base64 编码），按顺序拼接即可播放
+    Audio {
+        chunk: String,
+    },
+    // 一个词的朗读时间（毫秒，从整段音频开头算起），用于实时高亮
+    WordBoundary {
+        offset_ms: u64,
+        duration_ms: u64,
+        text: String,
+    },
+    // 全部音频已发送
+    Finished,
+}
+
+// 当前正在合成的一段音频对应的事件通道，offset 为这段音频之前的总时长（100纳秒）
+pub struct StreamTarget<'a> {
+    pub channel: &'a Channel<StreamEvent>,
+    pub offset: u64,
+}
+
+impl StreamTarget<'_> {
+    // 前端关闭页面后发送会失败，不影响合成
+    pub fn send(&self, event: StreamEvent) {
+        let _ = self.channel.send(event);
+    }
+}
+
+// 合成进度事件的名称，前端按 payload 中的 job_id 区分任务
+pub const PROGRESS_EVENT: &str = "tts-progress";
+
+#[derive(Clone, Serialize)]
+pub struct ProgressEvent {
+    pub job_id: String,
+    // 当前是第几段（从 1 开始），共几段
+    pub chunk: usize,
+    pub chunks: usize,
+    // 已朗读的字数和总字数（只计算字母、数字和汉字）
+    pub spoken_chars: usize,
+    pub total_chars: usize,
+    // 已生成的音频时长（秒）
+    pub audio_seconds: f64,
+}
+
+// 根据语音服务返回的词边界统计进度，通过 Tauri 事件发送给前端
+pub struct ProgressReporter {
+    app: AppHandle,
+    job_id: String,
+    chunks: usize,
+    total_chars: usize,
+    chunk: usize,
+    // 之前各段已朗读的字数，以及当前段已朗读的字数
+    done_chars: usize,
+    chunk_chars: usize,
+    // 当前段之前的音频时长和已生成的音频时长（100纳秒）
+    chunk_offset: u64,
+    audio_ticks: u64,
+}
+
+impl ProgressReporter {
+    // 创建后立即发送第一条进度事件，前端没有指定 job_id 时可以从中得到任务ID用于取消
+    pub fn new(app: AppHandle, job_id: &str, chunks: usize, text: &str) -> Self {
+        let reporter = ProgressReporter {
+            app,
+            job_id: job_id.to_string(),
+            chunks,
+            total_chars: count_chars(text),
+            chunk: 0,
+            done_chars: 0,
+            chunk_chars: 0,
+            chunk_offset: 0,
+            audio_ticks: 0,
+        };
+        reporter.emit();
+        reporter
+    }
+
+    // 开始合成第 index 段（从 0 开始）；同一段重新合成时重新计数
+    pub fn start_chunk(&mut self, index: usize, offset: u64) {
+        self.chunk = index + 1;
+        self.chunk_chars = 0;
+        self.chunk_offset = offset;
+        self.emit();
+    }
+
+    // 统计一条 audio.metadata 中的词边界
+    pub fn feed(&mut self, message: &Value) {
+        let metadata = match message.get("Metadata").and_then(|v| v.as_array()) {
+            Some(metadata) => metadata,
+            None => return,
+        };
+        for meta in metadata
+            .iter()
+            .filter(|meta| meta["Type"] == "WordBoundary")
+        {
+            let data = &meta["Data"];
+            self.chunk_chars += count_chars(data["text"]["Text"].as_str().unwrap_or(""));
+            let end = data["Offset"].as_u64().unwrap_or(0) + data["Duration"].as_u64().unwrap_or(0);
+            self.audio_ticks = self.audio_ticks.max(self.chunk_offset + end);
+        }
+        self.emit();
+    }
+
+    // 当前段合成完成，end 为这一段音频结束的时间
+    pub fn finish_chunk(&mut self, end: u64) {
+        self.done_chars += self.chunk_chars;
+        self.chunk_chars = 0;
+        self.audio_ticks = self.audio_ticks.max(end);
+        self.emit();
+    }
+
+    fn emit(&self) {
+        let _ = self.app.emit(
+            PROGRESS_EVENT,
+            ProgressEvent {
+                job_id: self.job_id.clone(),
+                chunk: self.chunk,
+                chunks: self.chunks,
+                // 朗读的词与原文不完全一致（如数字读法），不超过总字数
+                spoken_chars: (self.done_chars + self.chunk_chars).min(self.total_chars),
+                total_chars: self.total_chars,
+                audio_seconds: self.audio_ticks as f64 / 10_000_000.0,
+            },
+        );
+    }
+}
+
+fn count_chars(text: &str) -> usize {
+    text.chars().filter(|c| c.is_alphanumeric()).count()
+}
+
+// 批量合成中每一项状态变化时发送的事件
+pub const BATCH_EVENT: &str = "tts-batch";
+
+#[derive(Clone, Serialize)]
+pub struct BatchItemEvent {
+    pub batch_id: String,
+    pub index: usize,
+    pub file: String,
+    // queued、running、retrying、done、failed、cancelled
+    pub status: &'static str,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
@@ -0,0 +1,325 @@
+//! 批量合成任务的持久化：保存在前端使用的 SQLite 数据库中，应用重启后继续未完成的项
+
+use std::path::Path;
+use std::time::Duration;
+
+use super::api::TTSData;
+use super::custom_result::CustomResult;
+use chrono::Local;
+use serde_json::{json, Value};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+
+// 与前端 Database.load("sqlite:database.db") 是同一个文件，位于应用配置目录
+pub const DATABASE_FILE: &str = "database.db";
+
+const CREATE_TABLES: [&str; 2] = [
+    "CREATE TABLE IF NOT EXISTS tts_batch (
+        id TEXT PRIMARY KEY NOT NULL,
+        settings TEXT NOT NULL,
+        concurrency INTEGER NOT NULL,
+        retries INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        report_path TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS tts_batch_item (
+        batch_id TEXT NOT NULL,
+        idx INTEGER NOT NULL,
+        file TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        error TEXT,
+        output_path TEXT,
+        subtitle_path TEXT,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY (batch_id, idx)
+    )",
+];
+
+// 批量任务的状态：running 为未完成（重启后继续），finished、cancelled、failed 为已结束
+pub const BATCH_RUNNING: &str = "running";
+
+pub struct JobStore {
+    // 数据库打开失败时为错误信息，此时批量任务照常合成，但不保存也不会在重启后继续
+    pool: Result<SqlitePool, String>,
+}
+
+// 重启后需要继续的批量任务
+pub struct PendingBatch {
+    pub batch_id: String,
+    pub settings: TTSData,
+    pub concurrency: usize,
+    pub retries: u32,
+    pub items: Vec<PendingItem>,
+}
+
+// 尚未完成的一项，attempts 为重启前已经合成的次数
+pub struct PendingItem {
+    pub index: usize,
+    pub file: String,
+    pub attempts: u32,
+}
+
+impl JobStore {
+    pub async fn open(path: &Path) -> Result<JobStore, CustomResult> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| CustomResult::error(Some(format!("创建文件夹失败：{}", e)), None))?;
+        }
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            // 前端也在读写这个数据库，遇到锁时等待而不是直接失败
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await
+            .map_err(db_error)?;
+
+        for sql in CREATE_TABLES {
+            sqlx::query(sql).execute(&pool).await.map_err(db_error)?;
+        }
+
+        Ok(JobStore { pool: Ok(pool) })
+    }
+
+    pub fn unavailable(error: String) -> JobStore {
+        JobStore { pool: Err(error) }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.pool.is_ok()
+    }
+
+    fn pool(&self) -> Result<&SqlitePool, CustomResult> {
+        self.pool
+            .as_ref()
+            .map_err(|e| CustomResult::error(Some(format!("任务数据库不可用：{}", e)), None))
+    }
+
+    // 保存新的批量任务，所有项的状态为 queued
+    pub async fn create_batch(
+        &self,
+        batch_id: &str,
+        settings: &TTSData,
+        concurrency: usize,
+        retries: u32,
+        files: &[String],
+    ) -> Result<(), CustomResult> {
+        let settings = serde_json::to_string(settings)
+            .map_err(|e| CustomResult::error(Some(format!("序列化JSON失败：{}", e)), None))?;
+        let now = now();
+
+        let mut tx = self.pool()?.begin().await.map_err(db_error)?;
+        let exists = sqlx::query("SELECT 1 FROM tts_batch WHERE id = ?")
+            .bind(batch_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if exists.is_some() {
+            return Err(CustomResult::error(
+                Some(format!("批量任务ID已存在：{}", batch_id)),
+                None,
+            ));
+        }
+
+        sqlx::query(
+            "INSERT INTO tts_batch (id, settings, concurrency, retries, status, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(batch_id)
+        .bind(settings)
+        .bind(concurrency as i64)
+        .bind(retries as i64)
+        .bind(BATCH_RUNNING)
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        for (index, file) in files.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO tts_batch_item (batch_id, idx, file, status, updated_at)
+                 VALUES (?, ?, ?, 'queued', ?)",
+            )
+            .bind(batch_id)
+            .bind(index as i64)
+            .bind(file)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+
+        tx.commit().await.map_err(db_error)
+    }
+
+    // 更新一项的状态，result 为 run_tts 返回的数据（合成完成时才有输出文件）
+    pub async fn update_item(
+        &self,
+        batch_id: &str,
+        index: usize,
+        status: &str,
+        attempts: u32,
+        error: Option<&str>,
+        result: &Value,
+    ) -> Result<(), CustomResult> {
+        sqlx::query(
+            "UPDATE tts_batch_item
+             SET status = ?, attempts = ?, error = ?, output_path = ?, subtitle_path = ?, updated_at = ?
+             WHERE batch_id = ? AND idx = ?",
+        )
+        .bind(status)
+        .bind(attempts as i64)
+        .bind(error)
+        .bind(result["output_path"].as_str())
+        .bind(result["subtitle_path"].as_str())
+        .bind(now())
+        .bind(batch_id)
+        .bind(index as i64)
+        .execute(self.pool()?)
+        .await
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    pub async fn finish_batch(
+        &self,
+        batch_id: &str,
+        status: &str,
+        report_path: Option<&str>,
+    ) -> Result<(), CustomResult> {
+        sqlx::query(
+            "UPDATE tts_batch SET status = ?, report_path = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(status)
+        .bind(report_path)
+        .bind(now())
+        .bind(batch_id)
+        .execute(self.pool()?)
+        .await
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    // 上次运行时没有结束的批量任务。设置无法解析的任务（例如版本升级后格式变化）标记为 failed
+    pub async fn unfinished_batches(&self) -> Result<Vec<PendingBatch>, CustomResult> {
+        let rows = sqlx::query(
+            "SELECT id, settings, concurrency, retries FROM tts_batch
+             WHERE status = ? ORDER BY created_at",
+        )
+        .bind(BATCH_RUNNING)
+        .fetch_all(self.pool()?)
+        .await
+        .map_err(db_error)?;
+
+        let mut batches = Vec::new();
+        for row in rows {
+            let batch_id: String = row.get("id");
+            let settings: String = row.get("settings");
+            let settings = match serde_json::from_str::<TTSData>(&settings) {
+                Ok(settings) => settings,
+                Err(_) => {
+                    self.finish_batch(&batch_id, "failed", None).await?;
+                    continue;
+                }
+            };
+
+            let items = sqlx::query(
+                "SELECT idx, file, attempts FROM tts_batch_item
+                 WHERE batch_id = ? AND status NOT IN ('done', 'failed', 'cancelled')
+                 ORDER BY idx",
+            )
+            .bind(&batch_id)
+            .fetch_all(self.pool()?)
+            .await
+            .map_err(db_error)?
+            .iter()
+            .map(|row| PendingItem {
+                index: row.get::<i64, _>("idx") as usize,
+                file: row.get("file"),
+                attempts: row.get::<i64, _>("attempts") as u32,
+            })
+            .collect();
+
+            batches.push(PendingBatch {
+                batch_id,
+                settings,
+                concurrency: row.get::<i64, _>("concurrency") as usize,
+                retries: row.get::<i64, _>("retries") as u32,
+                items,
+            });
+        }
+
+        Ok(batches)
+    }
+
+    // 批量任务中的所有项，按序号排列
+    pub async fn batch_items(&self, batch_id: &str) -> Result<Vec<Value>, CustomResult> {
+        let rows = sqlx::query(
+            "SELECT idx, file, status, attempts, error, output_path, subtitle_path, updated_at
+             FROM tts_batch_item WHERE batch_id = ? ORDER BY idx",
+        )
+        .bind(batch_id)
+        .fetch_all(self.pool()?)
+        .await
+        .map_err(db_error)?;
+        Ok(rows.iter().map(item_json).collect())
+    }
+
+    // 最近的批量任务及其各项状态，供前端显示历史记录
+    pub async fn history(&self, limit: u32) -> Result<Vec<Value>, CustomResult> {
+        let rows = sqlx::query(
+            "SELECT id, settings, concurrency, retries, status, report_path, created_at, updated_at
+             FROM tts_batch ORDER BY created_at DESC LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await
+        .map_err(db_error)?;
+
+        let mut batches = Vec::new();
+        for row in rows {
+            let batch_id: String = row.get("id");
+            let settings: String = row.get("settings");
+            batches.push(json!({
+                "batch_id": batch_id,
+                "settings": serde_json::from_str::<Value>(&settings).unwrap_or(Value::Null),
+                "concurrency": row.get::<i64, _>("concurrency"),
+                "retries": row.get::<i64, _>("retries"),
+                "status": row.get::<String, _>("status"),
+                "report_path": row.get::<Option<String>, _>("report_path"),
+                "created_at": row.get::<String, _>("created_at"),
+                "updated_at": row.get::<String, _>("updated_at"),
+                "items": self.batch_items(&batch_id).await?,
+            }));
+        }
+
+        Ok(batches)
+    }
+}
+
+fn item_json(row: &SqliteRow) -> Value {
+    json!({
+        "index": row.get::<i64, _>("idx"),
+        "file": row.get::<String, _>("file"),
+        "status": row.get::<String, _>("status"),
+        "attempts": row.get::<i64, _>("attempts"),
+        "error": row.get::<Option<String>, _>("error"),
+        "output_path": row.get::<Option<String>, _>("output_path"),
+        "subtitle_path": row.get::<Option<String>, _>("subtitle_path"),
+        "updated_at": row.get::<String, _>("updated_at"),
+    })
+}
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn db_error(e: sqlx::Error) -> CustomResult {
+    CustomResult::error(Some(format!("数据库操作失败：{}", e)), None)
+}
@@ -0,0 +1,155 @@
+//! 正在进行的合成任务，用于取消任务
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::custom_result::CustomResult;
+use tokio::sync::watch;
+
+// 保存在 Tauri 的 managed state 中，键为任务 ID
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl JobRegistry {
+    pub fn register(&self, job_id: &str) -> Result<JobGuard<'_>, CustomResult> {
+        self.register_with_parent(job_id, None)
+    }
+
+    // 注册属于 parent 的子任务（如批量任务中的一项），parent 取消时子任务也随之取消，
+    // 注册前 parent 已经取消的，子任务一开始就处于取消状态
+    pub fn register_with_parent(
+        &self,
+        job_id: &str,
+        parent: Option<&CancelToken>,
+    ) -> Result<JobGuard<'_>, CustomResult> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(job_id) {
+            return Err(CustomResult::error(
+                Some(format!("任务已存在：{}", job_id)),
+                None,
+            ));
+        }
+
+        let (sender, receiver) = watch::channel(false);
+        jobs.insert(job_id.to_string(), sender);
+        Ok(JobGuard {
+            registry: self,
+            job_id: job_id.to_string(),
+            token: CancelToken {
+                receiver,
+                parent: parent.map(|parent| Box::new(parent.clone())),
+            },
+        })
+    }
+
+    // 通知任务取消，任务不存在（或已经结束）时返回 false
+    pub fn cancel(&self, job_id: &str) -> bool {
+        match self.jobs.lock().unwrap().get(job_id) {
+            Some(sender) => sender.send(true).is_ok(),
+            None => false,
+        }
+    }
+}
+
+// 任务结束（包括出错和取消）时从注册表中移除
+pub struct JobGuard<'a> {
+    registry: &'a JobRegistry,
+    job_id: String,
+    token: CancelToken,
+}
+
+impl JobGuard<'_> {
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        self.registry.jobs.lock().unwrap().remove(&self.job_id);
+    }
+}
+
+#[derive(Clone)]
+pub struct CancelToken {
+    receiver: watch::Receiver<bool>,
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+
+    // 任务或其父任务被取消时完成，否则一直等待
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        let own = async move {
+            if receiver.wait_for(|cancelled| *cancelled).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        match &self.parent {
+            Some(parent) => {
+                tokio::select! {
+                    _ = own => {}
+                    _ = Box::pin(parent.cancelled()) => {}
+                }
+            }
+            None => own.await,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), CustomResult> {
+        if self.is_cancelled() {
+            Err(CustomResult::cancelled(
+                Some("任务已取消".to_string()),
+                None,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancelled_parent_before_child_registers() {
+        let jobs = JobRegistry::default();
+        let batch = jobs.register("batch").expect("register batch");
+        // 子任务的 future 还没有开始执行时取消批量任务
+        let item = async {
+            let job = jobs
+                .register_with_parent("batch-1-1", Some(batch.token()))
+                .expect("register item");
+            job.token().check()
+        };
+        assert!(jobs.cancel("batch"));
+        assert!(item.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelling_parent_reaches_running_child() {
+        let jobs = JobRegistry::default();
+        let batch = jobs.register("batch").expect("register batch");
+        let job = jobs
+            .register_with_parent("batch-1-1", Some(batch.token()))
+            .expect("register item");
+        assert!(job.token().check().is_ok());
+
+        assert!(jobs.cancel("batch"));
+        tokio::time::timeout(std::time::Duration::from_secs(1), job.token().cancelled())
+            .await
+            .expect("child was not cancelled");
+        assert!(job.token().check().is_err());
+    }
+}
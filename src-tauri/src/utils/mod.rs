@@ -1,4 +1,12 @@
 pub mod api;
+pub mod audio;
+pub mod batch;
 pub mod custom_result;
+pub mod events;
+pub mod job_store;
+pub mod jobs;
+pub mod output_format;
+pub mod script;
+pub mod ssml;
 pub mod sub_marker;
 pub mod tts;
@@ -0,0 +1,154 @@
+//! 语音服务支持的音频输出格式
+
+use super::audio::riff_payload;
+use super::custom_result::CustomResult;
+
+pub const DEFAULT_OUTPUT_FORMAT: &str = "audio-24khz-48kbitrate-mono-mp3";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Container {
+    Mp3,
+    Webm,
+    Ogg,
+    // 没有文件头的 PCM 数据
+    RawPcm,
+    // 带 RIFF/WAVE 文件头的 PCM 数据
+    Riff,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutputFormat {
+    pub name: &'static str,
+    pub container: Container,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub channels: u16,
+    // 固定码率（kbit/s），仅 MP3 有效
+    pub bitrate: Option<u32>,
+}
+
+const fn mp3(name: &'static str, sample_rate: u32, bitrate: u32) -> OutputFormat {
+    OutputFormat {
+        name,
+        container: Container::Mp3,
+        sample_rate,
+        bits_per_sample: 16,
+        channels: 1,
+        bitrate: Some(bitrate),
+    }
+}
+
+const fn pcm(name: &'static str, container: Container, sample_rate: u32) -> OutputFormat {
+    OutputFormat {
+        name,
+        container,
+        sample_rate,
+        bits_per_sample: 16,
+        channels: 1,
+        bitrate: None,
+    }
+}
+
+const fn opus(name: &'static str, container: Container) -> OutputFormat {
+    OutputFormat {
+        name,
+        container,
+        sample_rate: 24000,
+        bits_per_sample: 16,
+        channels: 1,
+        bitrate: None,
+    }
+}
+
+pub const OUTPUT_FORMATS: &[OutputFormat] = &[
+    mp3("audio-24khz-48kbitrate-mono-mp3", 24000, 48),
+    mp3("audio-24khz-96kbitrate-mono-mp3", 24000, 96),
+    mp3("audio-48khz-96kbitrate-mono-mp3", 48000, 96),
+    mp3("audio-48khz-192kbitrate-mono-mp3", 48000, 192),
+    opus("webm-24khz-16bit-mono-opus", Container::Webm),
+    opus("ogg-24khz-16bit-mono-opus", Container::Ogg),
+    pcm("raw-16khz-16bit-mono-pcm", Container::RawPcm, 16000),
+    pcm("raw-24khz-16bit-mono-pcm", Container::RawPcm, 24000),
+    pcm("raw-48khz-16bit-mono-pcm", Container::RawPcm, 48000),
+    pcm("riff-16khz-16bit-mono-pcm", Container::Riff, 16000),
+    pcm("riff-24khz-16bit-mono-pcm", Container::Riff, 24000),
+    pcm("riff-48khz-16bit-mono-pcm", Container::Riff, 48000),
+];
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<&'static OutputFormat, CustomResult> {
+        OUTPUT_FORMATS
+            .iter()
+            .find(|format| format.name == name)
+            .ok_or_else(|| CustomResult::error(Some(format!("不支持的音频格式：{}", name)), None))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self.container {
+            Container::Mp3 => "mp3",
+            Container::Webm => "webm",
+            Container::Ogg => "ogg",
+            // PCM 数据会被包装成 WAV 文件
+            Container::RawPcm | Container::Riff => "wav",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self.container {
+            Container::Mp3 => "audio/mpeg",
+            Container::Webm => "audio/webm",
+            Container::Ogg => "audio/ogg",
+            Container::RawPcm | Container::Riff => "audio/wav",
+        }
+    }
+
+    // 流式播放时发送的数据类型，PCM 格式只发送不带文件头的 PCM 数据
+    pub fn stream_mime_type(&self) -> &'static str {
+        if self.is_pcm() {
+            "audio/pcm"
+        } else {
+            self.mime_type()
+        }
+    }
+
+    // 多段音频能否直接首尾拼接成一个文件。WebM 和 Ogg 拼接后是多个独立的流，
+    // 很多播放器和编辑软件只识别第一段
+    pub fn supports_concatenation(&self) -> bool {
+        !matches!(self.container, Container::Webm | Container::Ogg)
+    }
+
+    pub fn is_pcm(&self) -> bool {
+        matches!(self.container, Container::RawPcm | Container::Riff)
+    }
+
+    // 每秒音频的字节数，码率不固定的格式返回 None
+    pub fn bytes_per_second(&self) -> Option<u64> {
+        match self.container {
+            Container::Mp3 => self.bitrate.map(|kbit| kbit as u64 * 1000 / 8),
+            Container::RawPcm | Container::Riff => Some(
+                self.sample_rate as u64 * self.bits_per_sample as u64 / 8 * self.channels as u64,
+            ),
+            Container::Webm | Container::Ogg => None,
+        }
+    }
+
+    // 一段音频的时长（单位：100纳秒），码率不固定的格式返回 None
+    pub fn duration_ticks(&self, chunk: &[u8]) -> Option<u64> {
+        let payload = match self.container {
+            Container::Riff => riff_payload(chunk),
+            _ => chunk,
+        };
+        self.bytes_per_second()
+            .map(|bytes| payload.len() as u64 * 10_000_000 / bytes)
+    }
+
+    // 指定时长（单位：100纳秒）的 PCM 数据字节数，按采样对齐；非 PCM 格式返回 None
+    pub fn pcm_bytes(&self, ticks: u64) -> Option<usize> {
+        if !self.is_pcm() {
+            return None;
+        }
+        let block_align = (self.bits_per_sample / 8 * self.channels) as u64;
+        self.bytes_per_second()
+            .map(|bytes| (ticks * bytes / 10_000_000 / block_align * block_align) as usize)
+    }
+}
@@ -0,0 +1,102 @@
+//! 多角色对话脚本，每行形如 “角色: 台词”，每个角色使用各自的发音人
+
+use std::collections::HashMap;
+
+use super::custom_result::CustomResult;
+use super::ssml::validate_voice;
+use serde::{Deserialize, Serialize};
+
+// 角色使用的发音人，未设置的参数使用全局的音调、语速和音量
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpeakerVoice {
+    pub voice: String,
+    #[serde(default)]
+    pub pitch: Option<i32>,
+    #[serde(default)]
+    pub rate: Option<i32>,
+    #[serde(default)]
+    pub volume: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptLine {
+    pub speaker: String,
+    pub text: String,
+}
+
+// 解析对话脚本。角色名与台词之间用半角或全角冒号分隔，
+// 不像角色名的行（如含空格或网址）接在上一句台词后面，空行忽略
+pub fn parse_script(
+    script: &str,
+    speakers: &HashMap<String, SpeakerVoice>,
+) -> Result<Vec<ScriptLine>, CustomResult> {
+    let mut lines: Vec<ScriptLine> = Vec::new();
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let speaker_line = line
+            .split_once([':', '：'])
+            .map(|(speaker, text)| (speaker.trim(), text.trim()));
+
+        match (speaker_line, lines.last_mut()) {
+            (Some((speaker, text)), _) if speakers.contains_key(speaker) => {
+                lines.push(ScriptLine {
+                    speaker: speaker.to_string(),
+                    text: text.to_string(),
+                })
+            }
+            // 角色名拼写错误时不能当作上一句台词的一部分，否则会用错误的发音人朗读
+            (Some((speaker, text)), _) if looks_like_speaker(speaker, text) => {
+                return Err(CustomResult::error(
+                    Some(format!(
+                        "第{}行的角色“{}”没有设置发音人",
+                        number + 1,
+                        speaker
+                    )),
+                    None,
+                ));
+            }
+            (_, Some(last)) => {
+                last.text.push('\n');
+                last.text.push_str(line);
+            }
+            (_, None) => {
+                return Err(CustomResult::error(
+                    Some(format!(
+                        "第{}行缺少角色名，格式应为“角色: 台词”",
+                        number + 1
+                    )),
+                    None,
+                ));
+            }
+        }
+    }
+
+    lines.retain(|line| !line.text.trim().is_empty());
+    if lines.is_empty() {
+        return Err(CustomResult::error(
+            Some("脚本中没有可配音的台词".to_string()),
+            None,
+        ));
+    }
+
+    for line in lines.iter() {
+        validate_voice(&speakers[&line.speaker].voice)?;
+    }
+
+    Ok(lines)
+}
+
+// 冒号前的文字是否像角色名：不太长、不含空格、不是纯数字（如时间 10:30），
+// 冒号后不是网址的 //
+fn looks_like_speaker(speaker: &str, text: &str) -> bool {
+    !speaker.is_empty()
+        && speaker.chars().count() <= 20
+        && !speaker.contains(char::is_whitespace)
+        && !speaker.chars().all(|c| c.is_ascii_digit())
+        && !text.starts_with("//")
+}
@@ -0,0 +1,290 @@
+//! SSML 文档构建，负责转义文本与属性值，避免用户输入破坏 XML 结构
+
+use std::fmt;
+
+use super::custom_result::CustomResult;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde_json::json;
+
+// 用户自行编写 SSML 时允许使用的元素
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "speak", "voice", "prosody", "break", "emphasis", "say-as", "sub", "phoneme", "lang", "p",
+    "s", "mstts:silence", "mstts:express-as",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SsmlError {
+    // 转义后没有可朗读的文本
+    EmptyText,
+    // 发音人名称包含非法字符
+    InvalidVoice(String),
+    // 属性值中出现了 XML 无法表示的字符
+    InvalidAttribute(String),
+    // 文本中出现了 XML 无法表示的字符
+    InvalidCharacter(char),
+    // 用户提供的 SSML 不是合法的 XML 文档
+    InvalidDocument(String),
+    // 用户提供的 SSML 使用了不允许的元素
+    ElementNotAllowed(String),
+    // 用户提供的 SSML 超过单次请求的大小（字节数，上限）
+    DocumentTooLarge(usize, usize),
+}
+
+impl fmt::Display for SsmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SsmlError::EmptyText => write!(f, "要配音的文本为空"),
+            SsmlError::InvalidVoice(voice) => write!(f, "无效的发音人：{}", voice),
+            SsmlError::InvalidAttribute(value) => write!(f, "无效的属性值：{}", value),
+            SsmlError::InvalidCharacter(c) => {
+                write!(f, "文本中包含无法转换的字符：U+{:04X}", *c as u32)
+            }
+            SsmlError::InvalidDocument(msg) => write!(f, "SSML格式错误：{}", msg),
+            SsmlError::ElementNotAllowed(name) => write!(f, "SSML中不允许使用元素：<{}>", name),
+            SsmlError::DocumentTooLarge(size, max) => write!(
+                f,
+                "SSML文档过长（{}字节），单次最多{}字节，请拆分成多个文档",
+                size, max
+            ),
+        }
+    }
+}
+
+impl SsmlError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SsmlError::EmptyText => "emptyText",
+            SsmlError::InvalidVoice(_) => "invalidVoice",
+            SsmlError::InvalidAttribute(_) => "invalidAttribute",
+            SsmlError::InvalidCharacter(_) => "invalidCharacter",
+            SsmlError::InvalidDocument(_) => "invalidDocument",
+            SsmlError::ElementNotAllowed(_) => "elementNotAllowed",
+            SsmlError::DocumentTooLarge(_, _) => "documentTooLarge",
+        }
+    }
+}
+
+impl From<SsmlError> for CustomResult {
+    fn from(e: SsmlError) -> Self {
+        CustomResult::error(Some(e.to_string()), Some(json!({ "kind": e.kind() })))
+    }
+}
+
+pub struct SsmlBuilder {
+    voice: String,
+    lang: String,
+    pitch: i32,
+    rate: i32,
+    volume: i32,
+}
+
+impl SsmlBuilder {
+    pub fn new(voice: &str) -> Self {
+        SsmlBuilder {
+            voice: voice.to_string(),
+            lang: "en-US".to_string(),
+            pitch: 0,
+            rate: 0,
+            volume: 0,
+        }
+    }
+
+    pub fn pitch(mut self, pitch: i32) -> Self {
+        self.pitch = pitch;
+        self
+    }
+
+    pub fn rate(mut self, rate: i32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn volume(mut self, volume: i32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn build(&self, text: &str) -> Result<String, SsmlError> {
+        validate_voice(&self.voice)?;
+
+        let text = escape_text(text)?;
+        if text.trim().is_empty() {
+            return Err(SsmlError::EmptyText);
+        }
+
+        Ok(format!(
+            "<speak version='1.0' xmlns='http://www.w3.org/2001/10/synthesis' xml:lang='{}'><voice name='{}'><prosody pitch='{}Hz' rate='{}%' volume='{}%'>{}</prosody></voice></speak>",
+            escape_attribute(&self.lang)?,
+            escape_attribute(&self.voice)?,
+            signed(self.pitch),
+            signed(self.rate),
+            signed(self.volume),
+            text
+        ))
+    }
+}
+
+// 校验用户提供的 SSML 文档，返回其中可朗读的纯文本（用于生成字幕）
+pub fn validate_ssml(document: &str) -> Result<String, SsmlError> {
+    let invalid = |msg: String| SsmlError::InvalidDocument(msg);
+    let mut reader = Reader::from_str(document);
+    let mut stack: Vec<String> = Vec::new();
+    let mut has_root = false;
+    let mut text = String::new();
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| invalid(format!("位置 {}：{}", reader.buffer_position(), e)))?;
+
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                if !ALLOWED_ELEMENTS.contains(&name.as_str()) {
+                    return Err(SsmlError::ElementNotAllowed(name));
+                }
+                if stack.is_empty() {
+                    if has_root || name != "speak" {
+                        return Err(invalid("根元素必须是唯一的<speak>".to_string()));
+                    }
+                    has_root = true;
+                } else if name == "speak" {
+                    return Err(invalid("<speak>不能嵌套使用".to_string()));
+                }
+                for attr in e.attributes() {
+                    attr.map_err(|e| invalid(e.to_string()))?;
+                }
+                if matches!(event, Event::Start(_)) {
+                    stack.push(name);
+                }
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Text(e) => {
+                let content = e.unescape().map_err(|e| invalid(e.to_string()))?;
+                push_text(&stack, &content, &mut text)?;
+            }
+            // CDATA 中的文本同样会被朗读，位置要求与普通文本相同
+            Event::CData(e) => {
+                push_text(&stack, &String::from_utf8_lossy(&e), &mut text)?;
+            }
+            Event::DocType(_) | Event::PI(_) => {
+                return Err(invalid("不支持DOCTYPE或处理指令".to_string()));
+            }
+            Event::Decl(_) | Event::Comment(_) => {}
+            Event::Eof => break,
+        }
+    }
+
+    if let Some(name) = stack.last() {
+        return Err(invalid(format!("元素<{}>没有闭合", name)));
+    }
+    if !has_root {
+        return Err(invalid("缺少<speak>根元素".to_string()));
+    }
+    if text.trim().is_empty() {
+        return Err(SsmlError::EmptyText);
+    }
+
+    Ok(text)
+}
+
+// 检查文本所在的位置：<speak>之外只能有空白，非空白文本必须位于<voice>之内
+fn push_text(stack: &[String], content: &str, text: &mut String) -> Result<(), SsmlError> {
+    if stack.is_empty() {
+        if !content.trim().is_empty() {
+            return Err(SsmlError::InvalidDocument(
+                "<speak>之外不能有文本".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+    if !stack.iter().any(|name| name == "voice") && !content.trim().is_empty() {
+        return Err(SsmlError::InvalidDocument(
+            "文本必须位于<voice>元素之内".to_string(),
+        ));
+    }
+    text.push_str(content);
+    Ok(())
+}
+
+// 发音人名称形如 zh-CN-XiaoxiaoNeural 或
+// Microsoft Server Speech Text to Speech Voice (zh-CN, XiaoxiaoNeural)
+pub fn validate_voice(voice: &str) -> Result<(), SsmlError> {
+    let valid = !voice.trim().is_empty()
+        && voice.len() <= 128
+        && voice
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '(' | ')' | ','));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SsmlError::InvalidVoice(voice.to_string()))
+    }
+}
+
+// 转义文本节点，控制字符替换为空格
+pub fn escape_text(text: &str) -> Result<String, SsmlError> {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() && (c as u32) < 0x20 => escaped.push(' '),
+            c if !is_xml_char(c) => return Err(SsmlError::InvalidCharacter(c)),
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+// 转义属性值，属性值中不允许出现控制字符
+pub fn escape_attribute(value: &str) -> Result<String, SsmlError> {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() || !is_xml_char(c) => {
+                return Err(SsmlError::InvalidAttribute(value.to_string()))
+            }
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+// 单个字符作为文本节点转义后的字节长度
+pub fn escaped_char_len(c: char) -> usize {
+    match c {
+        '&' => 5,
+        '<' | '>' => 4,
+        _ => c.len_utf8(),
+    }
+}
+
+pub fn escaped_len(text: &str) -> usize {
+    text.chars().map(escaped_char_len).sum()
+}
+
+// XML 1.0 允许的字符范围
+fn is_xml_char(c: char) -> bool {
+    matches!(c as u32,
+        0x9 | 0xA | 0xD | 0x20..=0xD7FF | 0xE000..=0xFFFD | 0x10000..=0x10FFFF)
+}
+
+fn signed(value: i32) -> String {
+    if value >= 0 {
+        format!("+{}", value)
+    } else {
+        format!("{}", value)
+    }
+}
@@ -3,49 +3,775 @@ use std::fs::File;
 use std::io::Write;
 
 use super::custom_result::CustomResult;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// 时间单位为 100 纳秒（与 WordBoundary 的 Offset 一致），仅在输出时格式化
+const TICKS_PER_MS: u64 = 10_000;
+const TICKS_PER_CS: u64 = 100_000;
+// mergeByPause 默认的停顿时长（毫秒）
+pub const DEFAULT_PAUSE_MS: u64 = 500;
+
+// 与原文对齐后的一个词（或一组无法对齐的词），text_start..text_end 是其在原文中的字节范围
+#[derive(Debug, Clone)]
+struct TextSpan {
+    start: u64,
+    end: u64,
+    text_start: usize,
+    text_end: usize,
+}
 
 #[derive(Debug, Clone)]
 struct Cue {
     index: usize,
-    start: String,
-    end: String,
+    start: u64,
+    end: u64,
     content: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    Ass,
+    Lrc,
+}
+
+impl SubtitleFormat {
+    pub fn from_name(name: &str) -> Result<Self, CustomResult> {
+        match name {
+            "srt" => Ok(SubtitleFormat::Srt),
+            "vtt" => Ok(SubtitleFormat::Vtt),
+            "ass" => Ok(SubtitleFormat::Ass),
+            "lrc" => Ok(SubtitleFormat::Lrc),
+            _ => Err(CustomResult::error(
+                Some(format!("不支持的字幕格式：{}", name)),
+                None,
+            )),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+            SubtitleFormat::Ass => "ass",
+            SubtitleFormat::Lrc => "lrc",
+        }
+    }
+}
+
+// ASS 字幕的样式，颜色支持 #RRGGBB、#RRGGBBAA 或 ASS 原生的 &HAABBGGRR
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AssStyle {
+    pub font_name: String,
+    pub font_size: u32,
+    pub primary_colour: String,
+    pub secondary_colour: String,
+    pub outline_colour: String,
+    pub back_colour: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub outline: f32,
+    pub shadow: f32,
+    // 小键盘布局的对齐方式，1-9，2 为底部居中
+    pub alignment: u8,
+    pub margin_l: u32,
+    pub margin_r: u32,
+    pub margin_v: u32,
+    pub play_res_x: u32,
+    pub play_res_y: u32,
+}
+
+impl Default for AssStyle {
+    fn default() -> Self {
+        AssStyle {
+            font_name: "Microsoft YaHei".to_string(),
+            font_size: 60,
+            primary_colour: "#FFFFFF".to_string(),
+            secondary_colour: "#FFD700".to_string(),
+            outline_colour: "#000000".to_string(),
+            back_colour: "#00000080".to_string(),
+            bold: false,
+            italic: false,
+            outline: 2.0,
+            shadow: 0.0,
+            alignment: 2,
+            margin_l: 20,
+            margin_r: 20,
+            margin_v: 40,
+            play_res_x: 1920,
+            play_res_y: 1080,
+        }
+    }
+}
+
+impl AssStyle {
+    pub fn validate(&self) -> Result<(), CustomResult> {
+        let error = |msg: String| Err(CustomResult::error(Some(msg), None));
+
+        if self.font_name.trim().is_empty() || self.font_name.contains([',', '\n', '\r']) {
+            return error(format!("无效的字体名称：{}", self.font_name));
+        }
+        if self.font_size == 0 {
+            return error("字体大小必须大于0".to_string());
+        }
+        if !(1..=9).contains(&self.alignment) {
+            return error(format!("无效的对齐方式：{}", self.alignment));
+        }
+        if self.outline < 0.0 || self.shadow < 0.0 {
+            return error("描边和阴影不能为负数".to_string());
+        }
+        if self.play_res_x == 0 || self.play_res_y == 0 {
+            return error("视频分辨率必须大于0".to_string());
+        }
+        for colour in [
+            &self.primary_colour,
+            &self.secondary_colour,
+            &self.outline_colour,
+            &self.back_colour,
+        ] {
+            ass_colour(colour)?;
+        }
+
+        Ok(())
+    }
+
+    fn to_style_line(&self) -> Result<String, CustomResult> {
+        Ok(format!(
+            "Style: Default,{},{},{},{},{},{},{},{},0,0,100,100,0,0,1,{},{},{},{},{},{},1",
+            self.font_name.trim(),
+            self.font_size,
+            ass_colour(&self.primary_colour)?,
+            ass_colour(&self.secondary_colour)?,
+            ass_colour(&self.outline_colour)?,
+            ass_colour(&self.back_colour)?,
+            if self.bold { -1 } else { 0 },
+            if self.italic { -1 } else { 0 },
+            self.outline,
+            self.shadow,
+            self.alignment,
+            self.margin_l,
+            self.margin_r,
+            self.margin_v
+        ))
+    }
+}
+
+// 按约束条件合并字幕时的限制，宽度按显示宽度计算（中日韩文字记为 2）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MergeConstraints {
+    // 每行最大显示宽度
+    pub max_chars_per_line: usize,
+    // 每条字幕最多几行
+    pub max_lines: usize,
+    // 每条字幕最短、最长显示时间（毫秒）
+    pub min_duration_ms: u64,
+    pub max_duration_ms: u64,
+    // 每秒最多阅读的显示宽度
+    pub max_chars_per_second: f64,
+}
+
+impl Default for MergeConstraints {
+    fn default() -> Self {
+        MergeConstraints {
+            max_chars_per_line: 42,
+            max_lines: 2,
+            min_duration_ms: 1000,
+            max_duration_ms: 7000,
+            max_chars_per_second: 20.0,
+        }
+    }
+}
+
+impl MergeConstraints {
+    pub fn validate(&self) -> Result<(), CustomResult> {
+        let error = |msg: &str| Err(CustomResult::error(Some(msg.to_string()), None));
+
+        if self.max_chars_per_line == 0 || self.max_lines == 0 {
+            return error("每行字数和行数必须大于0");
+        }
+        if self.max_duration_ms == 0 || self.min_duration_ms > self.max_duration_ms {
+            return error("字幕最短显示时间不能大于最长显示时间");
+        }
+        if self.max_chars_per_second.is_nan() || self.max_chars_per_second <= 0.0 {
+            return error("每秒字数必须大于0");
+        }
+
+        Ok(())
+    }
+
+    // 文本能否按每行宽度折成不超过 max_lines 行（在空格、中日韩文字之间断行）
+    fn fits_lines(&self, text: &str) -> bool {
+        let mut lines = 1;
+        let mut line_width = 0;
+        for (unit, spaced) in break_units(text) {
+            let width = display_width(unit);
+            let needed = if line_width > 0 && spaced {
+                width + 1
+            } else {
+                width
+            };
+            if line_width + needed <= self.max_chars_per_line {
+                line_width += needed;
+                continue;
+            }
+            lines += 1;
+            line_width = width;
+            if width > self.max_chars_per_line || lines > self.max_lines {
+                return false;
+            }
+        }
+        true
+    }
+
+    // 在 duration（100纳秒）内读完文本不超过每秒最多阅读的宽度
+    fn fits_reading_speed(&self, text: &str, duration: u64) -> bool {
+        let seconds = duration as f64 / (TICKS_PER_MS * 1000) as f64;
+        display_width(text.trim()) as f64 <= self.max_chars_per_second * seconds
+    }
+
+    // 合并后的字幕是否满足行数、显示时间和阅读速度的限制
+    fn fits(&self, text: &str, start: u64, end: u64) -> bool {
+        let duration = end.saturating_sub(start);
+        duration <= self.max_duration_ms * TICKS_PER_MS
+            && self.fits_lines(text)
+            && self.fits_reading_speed(text, duration)
+    }
+}
+
+// 分句之后对字幕时间的调整，单位均为毫秒，0 表示不调整
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CueTiming {
+    // 与下一条字幕的间隔不超过该时长时，延长到下一条字幕开始，避免字幕闪烁
+    pub max_gap_fill_ms: u64,
+    // 每条字幕的最短显示时间，不会延长到与下一条字幕重叠
+    pub min_duration_ms: u64,
+    // 相邻两条字幕之间至少保留的间隔（通常为两帧）
+    pub min_gap_ms: u64,
+}
+
+impl CueTiming {
+    pub fn validate(&self) -> Result<(), CustomResult> {
+        if self.max_gap_fill_ms > 10_000 || self.min_duration_ms > 10_000 {
+            return Err(CustomResult::error(
+                Some("填补间隔和最短显示时间不能超过10秒".to_string()),
+                None,
+            ));
+        }
+        if self.min_gap_ms > 1000 {
+            return Err(CustomResult::error(
+                Some("字幕之间的最小间隔不能超过1秒".to_string()),
+                None,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_gap_fill_ms == 0 && self.min_duration_ms == 0 && self.min_gap_ms == 0
+    }
+}
+
+// 字幕生成相关的设置
+pub struct SubtitleOptions {
+    pub format: SubtitleFormat,
+    // WebVTT 的位置设置，例如 "line:90% align:center"
+    pub vtt_cue_settings: Option<String>,
+    pub ass_style: AssStyle,
+    // ASS 卡拉OK模式，为每个词添加 \k 标签
+    pub ass_karaoke: bool,
+    // 增强型 LRC，为每个词添加 <mm:ss.xx> 时间
+    pub lrc_enhanced: bool,
+    // mergeByConstraints 使用的限制条件
+    pub merge_constraints: MergeConstraints,
+    // 超过该显示宽度的字幕拆成两行，None 表示不换行
+    pub line_width: Option<usize>,
+    // 词与词之间的停顿超过该时长（毫秒）时另起一条字幕，
+    // mergeByPause 单独使用，其他分句方式下作为额外的拆分条件
+    pub pause_threshold_ms: Option<u64>,
+    // 填补间隔、最短显示时间和最小间隔
+    pub cue_timing: CueTiming,
+    // 整体时间偏移（毫秒，可为负数）和缩放比例，最后应用
+    pub time_offset_ms: i64,
+    pub time_scale: f64,
+}
+
 pub struct SubMaker {
     cues: Vec<Cue>,
+    // feed 得到的逐词字幕，合并后依然保留，用于卡拉OK等逐词效果
+    words: Vec<Cue>,
+    // 语音服务返回的句子边界
+    sentences: Vec<Cue>,
 }
 
 impl SubMaker {
     pub fn new() -> Self {
-        SubMaker { cues: Vec::new() }
+        SubMaker {
+            cues: Vec::new(),
+            words: Vec::new(),
+            sentences: Vec::new(),
+        }
     }
 
     pub fn feed(&mut self, msg: &serde_json::Value) -> Result<CustomResult, CustomResult> {
-        if msg["Type"] != "WordBoundary" {
+        if msg["Type"] != "WordBoundary" && msg["Type"] != "SentenceBoundary" {
             return Err(CustomResult::error(
-                Some("错误的Message类型，仅支持'WordBoundary'".to_string()),
+                Some("错误的Message类型，仅支持'WordBoundary'和'SentenceBoundary'".to_string()),
                 None,
             ));
         }
 
-        let offset = msg["Data"]["Offset"].as_u64().unwrap();
-        let duration = msg["Data"]["Duration"].as_u64().unwrap();
-        let text = msg["Data"]["text"]["Text"].as_str().unwrap().to_string();
+        let invalid = || CustomResult::error(Some("无效的字幕元数据".to_string()), None);
+        let offset = msg["Data"]["Offset"].as_u64().ok_or_else(invalid)?;
+        let duration = msg["Data"]["Duration"].as_u64().ok_or_else(invalid)?;
+        let text = msg["Data"]["text"]["Text"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .to_string();
 
-        let start = self.microseconds_to_time(offset);
-        let end = self.microseconds_to_time(offset + duration);
+        if msg["Type"] == "SentenceBoundary" {
+            self.sentences.push(Cue {
+                index: self.sentences.len() + 1,
+                start: offset,
+                end: offset + duration,
+                content: text,
+            });
+            return Ok(CustomResult::success(None, None));
+        }
 
-        self.cues.push(Cue {
+        let cue = Cue {
             index: self.cues.len() + 1,
-            start,
-            end,
+            start: offset,
+            end: offset + duration,
             content: text,
-        });
+        };
+        self.words.push(cue.clone());
+        self.cues.push(cue);
+
+        Ok(CustomResult::success(None, None))
+    }
+
+    // 读取 SRT 或 WebVTT 字幕（以 WEBVTT 开头时按 WebVTT 解析），去掉其中的样式标签。
+    // 字幕块之间用空白行（可以含空格）分隔；块内以第一个含有 --> 的时间行为准，
+    // 缺少空行时时间行前面的一行视为下一条字幕的序号
+    pub fn from_subtitle(content: &str) -> Result<Self, CustomResult> {
+        let content = content
+            .trim_start_matches('\u{feff}')
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        let is_vtt = content.trim_start().starts_with("WEBVTT");
+        let mut maker = SubMaker::new();
+
+        let mut blocks: Vec<Vec<&str>> = vec![Vec::new()];
+        for line in content.lines() {
+            match (line.trim().is_empty(), blocks.last_mut()) {
+                (true, Some(block)) if !block.is_empty() => blocks.push(Vec::new()),
+                (false, Some(block)) => block.push(line.trim()),
+                _ => {}
+            }
+        }
+
+        for (i, block) in blocks.iter().filter(|block| !block.is_empty()).enumerate() {
+            let first = block[0];
+            // 第一行含有 --> 的是时间行；之后只有格式正确的时间行才算下一条字幕，
+            // 其余含有 --> 的行是字幕文本
+            let timings: Vec<usize> = match block.iter().position(|line| line.contains("-->")) {
+                Some(first_timing) => std::iter::once(first_timing)
+                    .chain(
+                        (first_timing + 1..block.len())
+                            .filter(|&j| parse_timing_line(block[j]).is_some()),
+                    )
+                    .collect(),
+                None => Vec::new(),
+            };
+            // WebVTT 的文件头（文件头后面缺少空行时按字幕处理）、注释、样式和区域定义
+            if is_vtt
+                && ((i == 0 && timings.is_empty())
+                    || first == "NOTE"
+                    || first.starts_with("NOTE ")
+                    || first.starts_with("NOTE\t")
+                    || first == "STYLE"
+                    || first == "REGION")
+            {
+                continue;
+            }
+
+            if timings.is_empty() {
+                return Err(CustomResult::error(
+                    Some(format!(
+                        "第{}条字幕缺少时间行：{}",
+                        maker.cues.len() + 1,
+                        first
+                    )),
+                    None,
+                ));
+            }
+
+            for (k, &timing) in timings.iter().enumerate() {
+                let (start, end) = parse_timing_line(block[timing]).ok_or_else(|| {
+                    CustomResult::error(
+                        Some(format!(
+                            "第{}条字幕的时间格式错误：{}",
+                            maker.cues.len() + 1,
+                            block[timing]
+                        )),
+                        None,
+                    )
+                })?;
+                // 下一条时间行之前的一行是下一条字幕的序号或标识
+                let text_end = timings
+                    .get(k + 1)
+                    .map_or(block.len(), |next| (next - 1).max(timing + 1));
+
+                let text = block[timing + 1..text_end]
+                    .iter()
+                    .map(|line| strip_subtitle_tags(line))
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if text.is_empty() {
+                    continue;
+                }
+
+                maker.cues.push(Cue {
+                    index: maker.cues.len() + 1,
+                    start,
+                    end,
+                    content: text,
+                });
+            }
+        }
+
+        if maker.cues.is_empty() {
+            return Err(CustomResult::error(
+                Some("字幕文件中没有可配音的字幕".to_string()),
+                None,
+            ));
+        }
+
+        Ok(maker)
+    }
+
+    // 每条字幕用于配音的文本，多行合并成一行
+    pub fn cue_texts(&self) -> Vec<String> {
+        self.cues
+            .iter()
+            .map(|cue| {
+                cue.content
+                    .lines()
+                    .map(|line| line.trim())
+                    .fold(String::new(), |acc, line| join_words(&acc, line))
+            })
+            .collect()
+    }
+
+    // 把另一段的字幕追加到后面，speaker 不为空时字幕以“角色: ”开头
+    pub fn append(&mut self, other: SubMaker, speaker: Option<&str>) {
+        self.cues.extend(other.cues.into_iter().map(|mut cue| {
+            if let Some(speaker) = speaker {
+                cue.content = format!("{}: {}", speaker, cue.content.trim_start());
+            }
+            cue
+        }));
+        self.words.extend(other.words);
+        self.sentences.extend(other.sentences);
+        self.reset_index();
+    }
+
+    // 每条字幕的开始和结束时间（单位：100纳秒）
+    pub fn cue_times(&self) -> Vec<(u64, u64)> {
+        self.cues.iter().map(|cue| (cue.start, cue.end)).collect()
+    }
+
+    // 按实际合成的音频重新设置每条字幕的时间
+    pub fn set_cue_times(&mut self, times: &[(u64, u64)]) -> Result<CustomResult, CustomResult> {
+        if times.len() != self.cues.len() {
+            return Err(CustomResult::error(
+                Some(format!(
+                    "字幕数量不一致：{} 条字幕，{} 段音频",
+                    self.cues.len(),
+                    times.len()
+                )),
+                None,
+            ));
+        }
+
+        for (cue, (start, end)) in self.cues.iter_mut().zip(times) {
+            cue.start = *start;
+            cue.end = *end;
+        }
+
+        Ok(CustomResult::success(None, None))
+    }
+
+    // 只记录逐词时间（不生成字幕），用于导入字幕后的卡拉OK等逐词效果
+    pub fn load_words(&mut self, messages: &[serde_json::Value]) {
+        for msg in messages {
+            if let Some(metadata) = msg.get("Metadata").and_then(|v| v.as_array()) {
+                for meta in metadata
+                    .iter()
+                    .filter(|meta| meta["Type"] == "WordBoundary")
+                {
+                    let data = &meta["Data"];
+                    if let (Some(offset), Some(duration), Some(text)) = (
+                        data["Offset"].as_u64(),
+                        data["Duration"].as_u64(),
+                        data["text"]["Text"].as_str(),
+                    ) {
+                        self.words.push(Cue {
+                            index: self.words.len() + 1,
+                            start: offset,
+                            end: offset + duration,
+                            content: text.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn has_sentences(&self) -> bool {
+        !self.sentences.is_empty()
+    }
+
+    // 按语音服务返回的句子边界分句
+    pub fn merge_by_sentence(&mut self) -> Result<CustomResult, CustomResult> {
+        if self.sentences.is_empty() {
+            return Err(CustomResult::error(
+                Some("你选择了按句子边界分句，但语音服务没有返回句子边界".to_string()),
+                None,
+            ));
+        }
+
+        self.cues = self.sentences.clone();
+        self.reset_index();
+
+        Ok(CustomResult::success(None, None))
+    }
+
+    // 按约束条件把逐词字幕装入每条字幕：折行后超出行数、超过最长时间或
+    // 阅读速度过快时换下一条，之后再延长过短的字幕
+    pub fn merge_by_constraints(
+        &mut self,
+        constraints: &MergeConstraints,
+    ) -> Result<CustomResult, CustomResult> {
+        constraints.validate()?;
+        if self.words.is_empty() {
+            return Ok(CustomResult::success(None, None));
+        }
+
+        let max_duration = constraints.max_duration_ms * TICKS_PER_MS;
+        let mut new_cues: Vec<Cue> = Vec::new();
+        let mut current_cue = self.words[0].clone();
+
+        for word in self.words.iter().skip(1) {
+            let content = join_words(&current_cue.content, &word.content);
+            if constraints.fits(&content, current_cue.start, word.end) {
+                current_cue.end = word.end;
+                current_cue.content = content;
+            } else {
+                new_cues.push(current_cue);
+                current_cue = word.clone();
+            }
+        }
+        new_cues.push(current_cue);
+
+        // 过短的字幕尽量并入下一条
+        let min_duration = constraints.min_duration_ms * TICKS_PER_MS;
+        let mut merged: Vec<Cue> = Vec::new();
+        for cue in new_cues {
+            if let Some(last) = merged.last_mut() {
+                let content = join_words(&last.content, &cue.content);
+                if last.end - last.start < min_duration
+                    && constraints.fits(&content, last.start, cue.end)
+                {
+                    last.end = cue.end;
+                    last.content = content;
+                    continue;
+                }
+            }
+            merged.push(cue);
+        }
+
+        // 延长显示时间，使其满足最短时间和阅读速度，但不能与下一条重叠
+        for i in 0..merged.len() {
+            let next_start = merged.get(i + 1).map(|next| next.start);
+            let cue = &mut merged[i];
+            let reading = (display_width(cue.content.trim()) as f64
+                / constraints.max_chars_per_second
+                * 1000.0) as u64
+                * TICKS_PER_MS;
+            let wanted = cue.start + min_duration.max(reading).min(max_duration);
+            let limit = next_start.unwrap_or(u64::MAX);
+            if wanted > cue.end {
+                cue.end = wanted.min(limit).max(cue.end);
+            }
+        }
+
+        self.cues = merged;
+        self.reset_index();
+
+        Ok(CustomResult::success(None, None))
+    }
+
+    // 按停顿分句：相邻两个词之间的空隙超过 threshold_ms 时另起一条
+    pub fn merge_by_pause(&mut self, threshold_ms: u64) -> Result<CustomResult, CustomResult> {
+        if self.words.is_empty() {
+            return Ok(CustomResult::success(None, None));
+        }
+
+        let threshold = threshold_ms * TICKS_PER_MS;
+        let mut new_cues = Vec::new();
+        let mut current_cue = self.words[0].clone();
+
+        for word in self.words.iter().skip(1) {
+            if word.start.saturating_sub(current_cue.end) > threshold {
+                new_cues.push(current_cue);
+                current_cue = word.clone();
+            } else {
+                current_cue.end = word.end;
+                current_cue.content = join_words(&current_cue.content, &word.content);
+            }
+        }
+
+        new_cues.push(current_cue);
+        self.cues = new_cues;
+        self.reset_index();
 
         Ok(CustomResult::success(None, None))
     }
 
+    // 在已合并的字幕内部，遇到超过 threshold_ms 的停顿时拆成多条
+    pub fn split_by_pause(&mut self, threshold_ms: u64) {
+        let threshold = threshold_ms * TICKS_PER_MS;
+        let mut new_cues: Vec<Cue> = Vec::new();
+
+        for cue in self.cues.iter() {
+            let syllables = self.cue_syllables(cue);
+            if syllables.is_empty() {
+                new_cues.push(cue.clone());
+                continue;
+            }
+
+            let mut current: Option<Cue> = None;
+            for (start, end, text) in syllables {
+                match current.as_mut() {
+                    Some(part) if start.saturating_sub(part.end) <= threshold => {
+                        part.end = end;
+                        part.content.push_str(&text);
+                    }
+                    _ => {
+                        if let Some(part) = current.take() {
+                            new_cues.push(part);
+                        }
+                        current = Some(Cue {
+                            index: 0,
+                            start,
+                            end,
+                            content: text.trim_start().to_string(),
+                        });
+                    }
+                }
+            }
+            if let Some(mut part) = current {
+                // 保留原字幕的结束时间（可能已被延长）
+                part.end = part.end.max(cue.end);
+                new_cues.push(part);
+            }
+        }
+
+        self.cues = new_cues;
+        self.reset_index();
+    }
+
+    // 依次填补短间隔、保证最短显示时间、保证最小间隔，只调整结束时间
+    pub fn adjust_timing(&mut self, timing: &CueTiming) {
+        let max_gap_fill = timing.max_gap_fill_ms * TICKS_PER_MS;
+        let min_duration = timing.min_duration_ms * TICKS_PER_MS;
+        let min_gap = timing.min_gap_ms * TICKS_PER_MS;
+
+        for i in 0..self.cues.len() {
+            let next_start = self.cues.get(i + 1).map(|next| next.start);
+            let cue = &mut self.cues[i];
+
+            if let Some(next_start) = next_start {
+                if cue.end < next_start && next_start - cue.end <= max_gap_fill {
+                    cue.end = next_start;
+                }
+            }
+
+            // 不能延长到下一条字幕的最小间隔之内
+            let limit = next_start.map(|next_start| next_start.saturating_sub(min_gap));
+            if cue.end.saturating_sub(cue.start) < min_duration {
+                let end = cue.start + min_duration;
+                cue.end = limit.map_or(end, |limit| end.min(limit).max(cue.end));
+            }
+
+            if let Some(limit) = limit {
+                if cue.end > limit {
+                    cue.end = limit.max(cue.start);
+                }
+            }
+        }
+    }
+
+    // 所有时间先乘以 scale 再加上 offset_ms，用于片头之后的音频或变速后的视频。
+    // 任何一条字幕的开始时间变成负数时报错，不修改字幕
+    pub fn retime(&mut self, offset_ms: i64, scale: f64) -> Result<CustomResult, CustomResult> {
+        if !scale.is_finite() || scale <= 0.0 {
+            return Err(CustomResult::error(
+                Some("时间缩放比例必须大于0".to_string()),
+                None,
+            ));
+        }
+
+        let offset = offset_ms as f64 * TICKS_PER_MS as f64;
+        let map = |ticks: u64| (ticks as f64 * scale + offset).round();
+
+        // 缩放比例为正数，时间顺序不变，只需检查最早的开始时间
+        let earliest = self
+            .cues
+            .iter()
+            .chain(self.words.iter())
+            .chain(self.sentences.iter())
+            .min_by_key(|cue| cue.start);
+        if let Some(cue) = earliest {
+            if map(cue.start) < 0.0 {
+                return Err(CustomResult::error(
+                    Some(format!(
+                        "时间偏移后字幕“{}”的开始时间为负数",
+                        cue.content.trim()
+                    )),
+                    None,
+                ));
+            }
+        }
+
+        for cue in self
+            .cues
+            .iter_mut()
+            .chain(self.words.iter_mut())
+            .chain(self.sentences.iter_mut())
+        {
+            cue.start = map(cue.start) as u64;
+            cue.end = map(cue.end) as u64;
+        }
+
+        Ok(CustomResult::success(None, None))
+    }
+
+    // 把过长的字幕拆成两行，尽量在标点、空格处断开并使两行长度接近
+    pub fn wrap_lines(&mut self, max_width: usize) {
+        for cue in self.cues.iter_mut() {
+            cue.content = wrap_text(cue.content.trim(), max_width);
+        }
+    }
+
     pub fn merge_by_number(&mut self, words: i32) -> Result<CustomResult, CustomResult> {
         if words == 0 || self.cues.is_empty() {
             return Ok(CustomResult::success(None, None));
@@ -58,7 +784,7 @@ impl SubMaker {
             let word_count = current_cue.content.split_whitespace().count();
 
             if word_count < words.try_into().unwrap() {
-                current_cue.end = cue.end.clone();
+                current_cue.end = cue.end;
                 current_cue.content = format!("{} {}", current_cue.content, cue.content);
             } else {
                 new_cues.push(current_cue);
@@ -73,6 +799,9 @@ impl SubMaker {
         Ok(CustomResult::success(None, None))
     }
 
+    // 按原文中的标点分句。先把逐词字幕与原文对齐（忽略大小写、空格和标点），
+    // 对不上的词（数字读法、缩写等）归入前后两个已对齐的词之间的原文，
+    // 这样原文不会丢失；无法对齐的片段通过返回值的 unaligned 报告
     pub fn merge_by_punctuation(&mut self, all_text: &str) -> Result<CustomResult, CustomResult> {
         if all_text.is_empty() {
             return Err(CustomResult::error(
@@ -81,60 +810,69 @@ impl SubMaker {
             ));
         }
 
-        let mut new_cues: Vec<Cue> = Vec::new();
-        let mut current_index = 0;
-        let mut last_match_end: isize = -1;
-        let mut match_start_index = 0;
         let punctuation_re = regex::Regex::new(r#"[。！？？，；,()\[\]（）【】{}、\.\?!;:<>《》「」『』“”‘’"…\n]+"#).map_err(|e| CustomResult::error(Some(e.to_string()), None))?;
+        let is_punctuation = |c: char| punctuation_re.is_match(c.encode_utf8(&mut [0; 4]));
 
-        for i in 0..self.cues.len() {
-            let cue = &self.cues[i];
-            let search_text = &cue.content;
-
-            if let Some(position) = all_text[current_index..].find(search_text) {
-                let position = current_index + position;
-                if last_match_end == -1 {
-                    last_match_end = position as isize;
-                }
+        let (spans, unaligned) = align_words(&self.words, all_text);
+        let mut new_cues: Vec<Cue> = Vec::new();
+        if spans.is_empty() {
+            self.cues = new_cues;
+            return Ok(CustomResult::success(
+                None,
+                Some(json!({ "unaligned": unaligned })),
+            ));
+        }
 
-                let mut next_position = position + search_text.len();
-                let mut max_while = 0;
-                let mut chars = all_text[next_position..].chars();
+        // 第一个词之前的原文（去掉标点）归入第一条字幕
+        let mut cue_text_start = content_start(&all_text[..spans[0].text_start], is_punctuation);
+        let mut cue_start = spans[0].start;
 
-                // 如果是英文，这里有时会变成空格，需要过滤一下
-                while let Some(next_char) = chars.next() {
-                    // 如果不是空格 或 超过了最大限制则退出
-                    if next_char != ' ' || max_while == 10 {
-                        break;
-                    }
-                    next_position += next_char.len_utf8();
-                    max_while += 1;
-                }
+        for (i, span) in spans.iter().enumerate() {
+            let next = spans.get(i + 1);
+            let gap_end = next.map_or(all_text.len(), |next| next.text_start);
+            let gap = &all_text[span.text_end..gap_end];
 
-                // 判断下一个字符是否是标点
-                let next_char = all_text[next_position..].chars().next().unwrap_or(' ');
-                if punctuation_re.is_match(&next_char.to_string()) {
-                    let start = self.cues[match_start_index].start.clone();
-                    let end_pos = next_position;
-                    let extracted_text = &all_text[last_match_end as usize..end_pos];
-                    new_cues.push(Cue {
-                        index: cue.index,
-                        start,
-                        end: cue.end.clone(),
-                        content: extracted_text.to_string(),
-                    });
-                    last_match_end = -1;
-                    match_start_index = i + 1;
+            let text_end = match next {
+                Some(next) => {
+                    // 两个词之间没有标点，继续当前字幕
+                    let last_punctuation =
+                        match gap.char_indices().rev().find(|(_, c)| is_punctuation(*c)) {
+                            Some(last) => last,
+                            None => continue,
+                        };
+                    // 在最后一个标点处断开，标点本身不进入字幕，标点之间的原文留在当前字幕
+                    let text_end =
+                        span.text_end + content_end(&gap[..last_punctuation.0], is_punctuation);
+                    push_text_cue(
+                        &mut new_cues,
+                        cue_start,
+                        span.end,
+                        &all_text[cue_text_start..text_end],
+                    );
+                    cue_text_start =
+                        span.text_end + last_punctuation.0 + last_punctuation.1.len_utf8();
+                    cue_start = next.start;
+                    continue;
                 }
+                // 最后一个词之后的原文（去掉标点）归入最后一条字幕
+                None => span.text_end + content_end(gap, is_punctuation),
+            };
 
-                current_index = position;
-            }
+            push_text_cue(
+                &mut new_cues,
+                cue_start,
+                span.end,
+                &all_text[cue_text_start..text_end],
+            );
         }
 
         self.cues = new_cues;
         self.reset_index();
 
-        Ok(CustomResult::success(None, None))
+        Ok(CustomResult::success(
+            None,
+            Some(json!({ "unaligned": unaligned })),
+        ))
     }
 
     pub fn get_srt(&self) -> String {
@@ -144,8 +882,8 @@ impl SubMaker {
                 format!(
                     "{}\n{} --> {}\n{}\n",
                     cue.index,
-                    cue.start,
-                    cue.end,
+                    srt_time(cue.start),
+                    srt_time(cue.end),
                     cue.content.trim()
                 )
             })
@@ -153,54 +891,1219 @@ impl SubMaker {
             .join("\n")
     }
 
+    // cue_settings 为 WebVTT 的位置设置，例如 "line:90% align:center"
+    pub fn get_vtt(&self, cue_settings: Option<&str>) -> String {
+        let settings = cue_settings
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| format!(" {}", s))
+            .unwrap_or_default();
+
+        let cues = self
+            .cues
+            .iter()
+            .map(|cue| {
+                format!(
+                    "{}\n{} --> {}{}\n{}\n",
+                    cue.index,
+                    vtt_time(cue.start),
+                    vtt_time(cue.end),
+                    settings,
+                    escape_vtt_text(cue.content.trim())
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!("WEBVTT\n\n{}", cues)
+    }
+
+    pub fn get_ass(&self, style: &AssStyle, karaoke: bool) -> Result<String, CustomResult> {
+        let mut lines = vec![
+            "[Script Info]".to_string(),
+            "ScriptType: v4.00+".to_string(),
+            format!("PlayResX: {}", style.play_res_x),
+            format!("PlayResY: {}", style.play_res_y),
+            "WrapStyle: 0".to_string(),
+            "ScaledBorderAndShadow: yes".to_string(),
+            String::new(),
+            "[V4+ Styles]".to_string(),
+            "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding".to_string(),
+            style.to_style_line()?,
+            String::new(),
+            "[Events]".to_string(),
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text"
+                .to_string(),
+        ];
+
+        for cue in self.cues.iter() {
+            let text = if karaoke {
+                self.karaoke_text(cue)
+            } else {
+                escape_ass_text(cue.content.trim())
+            };
+            lines.push(format!(
+                "Dialogue: 0,{},{},Default,,0,0,0,,{}",
+                ass_time(cue.start),
+                ass_time(cue.end),
+                text
+            ));
+        }
+
+        Ok(lines.join("\n") + "\n")
+    }
+
+    // 根据逐词字幕生成带 \k 标签的文本，\k 的单位为百分之一秒
+    fn karaoke_text(&self, cue: &Cue) -> String {
+        let syllables = self.cue_syllables(cue);
+        if syllables.is_empty() {
+            return escape_ass_text(cue.content.trim());
+        }
+
+        // 以百分之一秒为单位取整后再相减，避免误差累积
+        let to_cs =
+            |ticks: u64| (ticks.saturating_sub(cue.start) + TICKS_PER_CS / 2) / TICKS_PER_CS;
+        let mut last_cs = 0;
+        let mut result = String::new();
+
+        for (start, end, text) in syllables.iter() {
+            let start_cs = to_cs(*start);
+            let end_cs = to_cs(*end).max(start_cs);
+            if start_cs > last_cs {
+                result.push_str(&format!("{{\\k{}}}", start_cs - last_cs));
+            }
+            result.push_str(&format!(
+                "{{\\k{}}}{}",
+                end_cs - start_cs,
+                escape_ass_text(text)
+            ));
+            last_cs = end_cs;
+        }
+
+        result
+    }
+
+    // 把字幕拆回逐词的 (开始时间, 结束时间, 文本)，文本取自原文（包含标点），
+    // 所有词的文本连起来就是整条字幕
+    fn cue_syllables(&self, cue: &Cue) -> Vec<(u64, u64, String)> {
+        let words: Vec<&Cue> = self
+            .words
+            .iter()
+            .filter(|word| word.start >= cue.start && word.start < cue.end)
+            .collect();
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        // 每个词在原文中的位置，朗读的词与原文不同时（如 $5 读作 five dollars）为 None
+        let content = cue.content.trim();
+        let mut search_from = 0;
+        let positions: Vec<Option<(usize, usize)>> = words
+            .iter()
+            .map(|word| {
+                let position = content[search_from..].find(word.content.as_str())?;
+                let start = search_from + position;
+                search_from = start + word.content.len();
+                Some((start, search_from))
+            })
+            .collect();
+
+        // 每个词的文本从上一个词结束处开始；找不到的词分摊前后两个词之间的原文，
+        // 最后一个词包含剩余的全部原文
+        let mut texts: Vec<&str> = Vec::with_capacity(words.len());
+        let mut begin = 0;
+        let mut i = 0;
+        while i < words.len() {
+            if let Some((_, end)) = positions[i] {
+                let end = if i == words.len() - 1 {
+                    content.len()
+                } else {
+                    end
+                };
+                texts.push(&content[begin..end]);
+                begin = end;
+                i += 1;
+                continue;
+            }
+            let run_end = (i..words.len())
+                .find(|&j| positions[j].is_some())
+                .unwrap_or(words.len());
+            let gap_end = match positions.get(run_end) {
+                Some(Some((start, _))) => *start,
+                _ => content.len(),
+            };
+            texts.extend(split_gap(&content[begin..gap_end], run_end - i));
+            begin = gap_end;
+            i = run_end;
+        }
+
+        words
+            .iter()
+            .zip(texts)
+            .map(|(word, text)| (word.start, word.end, text.to_string()))
+            .collect()
+    }
+
+    // enhanced 为 true 时生成增强型 LRC，每个词前带 <mm:ss.xx> 时间
+    pub fn get_lrc(&self, enhanced: bool) -> String {
+        let mut lines = Vec::new();
+
+        for (i, cue) in self.cues.iter().enumerate() {
+            let (start, end) = (cue.start, cue.end);
+            let syllables = if enhanced {
+                self.cue_syllables(cue)
+            } else {
+                Vec::new()
+            };
+
+            let text = if syllables.is_empty() {
+                single_line(cue.content.trim())
+            } else {
+                syllables
+                    .iter()
+                    .map(|(start, _, text)| {
+                        let text = single_line(text);
+                        let leading = text.len() - text.trim_start().len();
+                        // 词前的空格放在时间标签之前
+                        format!(
+                            "{}<{}>{}",
+                            &text[..leading],
+                            lrc_time(*start),
+                            &text[leading..]
+                        )
+                    })
+                    .collect::<String>()
+            };
+            lines.push(format!("[{}]{}", lrc_time(start), text));
+
+            // 与下一句之间有空隙时，加一个空行清除歌词
+            let next_start = self.cues.get(i + 1).map(|next| next.start);
+            if !matches!(next_start, Some(next) if next <= end) {
+                lines.push(format!("[{}]", lrc_time(end)));
+            }
+        }
+
+        lines.join("\n") + "\n"
+    }
+
     fn reset_index(&mut self) {
         for (i, cue) in self.cues.iter_mut().enumerate() {
             cue.index = i + 1;
         }
     }
+}
+
+// 解析 "00:00:01,000 --> 00:00:02,500 align:center" 这样的时间行，忽略 WebVTT 的位置设置
+fn parse_timing_line(line: &str) -> Option<(u64, u64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    let start = parse_timestamp(start.trim())?;
+    let end = parse_timestamp(end)?;
+    (end >= start).then_some((start, end))
+}
+
+// 支持 HH:MM:SS,mmm、HH:MM:SS.mmm 和 WebVTT 的 MM:SS.mmm，返回 100 纳秒
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let (clock, fraction) = text.split_once([',', '.']).unwrap_or((text, "0"));
+    if fraction.is_empty() || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let milliseconds: u64 = format!("{:0<3}", fraction).parse().ok()?;
+
+    let parts = clock
+        .split(':')
+        .map(|part| part.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+
+    Some((((hours * 60 + minutes) * 60 + seconds) * 1000 + milliseconds) * TICKS_PER_MS)
+}
+
+// 去掉 <i>、<font ...>、<c.yellow>、<00:00:01.000> 等标签和 ASS 的 {\an8} 覆盖标签，还原实体。
+// 后面没有对应的 > 或 } 时不是标签，原样保留（例如 5 < 6）
+fn strip_subtitle_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut closing = None;
+    for (i, c) in line.char_indices() {
+        match (closing, c) {
+            (None, '<') if line[i + 1..].contains('>') => closing = Some('>'),
+            (None, '{') if line[i + 1..].starts_with('\\') && line[i + 1..].contains('}') => {
+                closing = Some('}')
+            }
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+            (None, c) => text.push(c),
+        }
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+// 把原文按空白分给 count 个找不到位置的词，最后一个词包含剩余部分
+fn split_gap(gap: &str, count: usize) -> Vec<&str> {
+    let mut pieces: Vec<&str> = Vec::with_capacity(count);
+    let mut rest = gap;
+    while pieces.len() + 1 < count && !rest.trim().is_empty() {
+        let leading = rest.len() - rest.trim_start().len();
+        let end = rest[leading..]
+            .find(char::is_whitespace)
+            .map(|position| leading + position)
+            .unwrap_or(rest.len());
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    pieces.push(rest);
+    pieces.resize(count, "");
+    pieces
+}
+
+// WebVTT 的位置设置会原样写入时间行，只允许空格分隔的 key:value
+pub fn validate_vtt_cue_settings(settings: &str) -> Result<(), CustomResult> {
+    const KEYS: [&str; 6] = ["vertical", "line", "position", "size", "align", "region"];
+    let invalid = |msg: String| Err(CustomResult::error(Some(msg), None));
+
+    if settings.contains(['\n', '\r']) || settings.contains("-->") {
+        return invalid("WebVTT位置设置不能包含换行或-->".to_string());
+    }
+    for setting in settings.split_whitespace() {
+        match setting.split_once(':') {
+            Some((key, value)) if KEYS.contains(&key) && !value.is_empty() => {}
+            _ => {
+                return invalid(format!(
+                    "WebVTT位置设置“{}”无效，应为 {} 之一，格式为 key:value",
+                    setting,
+                    KEYS.join("、")
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// WebVTT 的文本中 & < > 需要转义
+fn escape_vtt_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// LRC 的时间格式 mm:ss.xx，分钟数可以超过 99
+fn lrc_time(ticks: u64) -> String {
+    let centiseconds = round_to_cs(ticks);
+    let minutes = centiseconds / 6000;
+    let seconds = (centiseconds % 6000) / 100;
+    let cs = centiseconds % 100;
+    format!("{:02}:{:02}.{:02}", minutes, seconds, cs)
+}
+
+// LRC 每句只能占一行
+fn single_line(text: &str) -> String {
+    text.replace("\r\n", " ").replace(['\n', '\r'], " ")
+}
+
+// 显示宽度，中日韩文字记为 2，控制字符不计
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if is_cjk(c) { 2 } else { 1 })
+        .sum()
+}
+
+// 断行的最小单位及其前面是否有空格：以空格分隔的词，中日韩文字逐字断开，
+// 标点跟随前面的文字
+fn break_units(text: &str) -> Vec<(&str, bool)> {
+    let mut units = Vec::new();
+    for token in text.split_whitespace() {
+        let chars: Vec<(usize, char)> = token.char_indices().collect();
+        let mut start = 0;
+        for (k, (_, c)) in chars.iter().enumerate() {
+            let (end, breakable) = match chars.get(k + 1) {
+                Some((next_index, next)) => (
+                    *next_index,
+                    (is_cjk(*c) || is_cjk(*next)) && !is_break_punctuation(*next),
+                ),
+                None => (token.len(), true),
+            };
+            if breakable {
+                units.push((&token[start..end], start == 0));
+                start = end;
+            }
+        }
+    }
+    units
+}
+
+// 拼接两个词，非中日韩文字之间用空格分隔
+fn join_words(left: &str, right: &str) -> String {
+    match (left.chars().last(), right.chars().next()) {
+        (Some(l), Some(r))
+            if !is_cjk(l) && !is_cjk(r) && !l.is_whitespace() && !r.is_whitespace() =>
+        {
+            format!("{} {}", left, right)
+        }
+        _ => format!("{}{}", left, right),
+    }
+}
+
+// 适合作为行尾的标点，断行时优先在其后断开，也不应出现在行首
+fn is_break_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        '，' | '。'
+            | '、'
+            | '；'
+            | '：'
+            | '！'
+            | '？'
+            | '…'
+            | '）'
+            | '」'
+            | '』'
+            | '”'
+            | '’'
+            | '》'
+            | ','
+            | '.'
+            | ';'
+            | ':'
+            | '!'
+            | '?'
+            | ')'
+    )
+}
+
+// 文本超过 max_width 时拆成两行，返回用 \n 连接的文本
+fn wrap_text(text: &str, max_width: usize) -> String {
+    // 原有的换行先合并成一行
+    let text = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .fold(String::new(), |acc, line| join_words(&acc, line));
+    if display_width(&text) <= max_width {
+        return text;
+    }
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    // (得分, 第一行结束, 第二行开始)
+    let mut best: Option<(usize, usize, usize)> = None;
+    let mut first = 0;
+
+    for i in 0..chars.len().saturating_sub(1) {
+        let (_, c) = chars[i];
+        let (next_index, next) = chars[i + 1];
+        first += display_width(&c.to_string());
+
+        // 断点的代价：标点后最好，其次是空格，最后是两个中日韩文字之间
+        let (penalty, second_start) = if next.is_whitespace() {
+            let after = chars[i + 1..]
+                .iter()
+                .find(|(_, c)| !c.is_whitespace())
+                .map(|(index, _)| *index)
+                .unwrap_or(text.len());
+            (if is_break_punctuation(c) { 0 } else { 4 }, after)
+        } else if is_break_punctuation(next) || c.is_whitespace() {
+            continue;
+        } else if is_break_punctuation(c) && (is_cjk(c) || is_cjk(next)) {
+            (0, next_index)
+        } else if is_cjk(c) && is_cjk(next) {
+            (8, next_index)
+        } else {
+            continue;
+        };
+
+        let second = display_width(&text[second_start..]);
+        let overflow = first.saturating_sub(max_width) + second.saturating_sub(max_width);
+        let score = first.abs_diff(second) + penalty + overflow * 1000;
+        if !matches!(best, Some((best_score, _, _)) if best_score <= score) {
+            best = Some((score, next_index, second_start));
+        }
+    }
+
+    match best {
+        Some((_, first_end, second_start)) => format!(
+            "{}\n{}",
+            text[..first_end].trim_end(),
+            text[second_start..].trim_start()
+        ),
+        None => text,
+    }
+}
+
+// 对齐用的归一化：忽略大小写，全角字母数字转半角，只保留字母和数字
+fn normalize_char(c: char) -> impl Iterator<Item = char> {
+    let c = match c as u32 {
+        0xFF01..=0xFF5E => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    };
+    c.to_lowercase().filter(|c| c.is_alphanumeric())
+}
+
+// 归一化后的原文，每个字符记录其在原文中的字节范围
+fn normalize_with_positions(text: &str) -> Vec<(char, usize, usize)> {
+    text.char_indices()
+        .flat_map(|(i, c)| normalize_char(c).map(move |n| (n, i, i + c.len_utf8())))
+        .collect()
+}
+
+// 在窗口内找不到时用作锚点的短语：至少这么多个字符，最多这么多个词
+const ANCHOR_MIN_CHARS: usize = 8;
+const ANCHOR_MAX_WORDS: usize = 4;
+
+// 把逐词字幕与原文对齐。每个词先在游标之后的有限范围内查找；找不到时，
+// 用这个词和后面几个词组成的短语在剩余原文中查找，短语只出现一次时从该处
+// 重新对齐，这样一段没有朗读的长文本（如网址）不会使后面的词全部对不上。
+// 仍然找不到的词（数字读法、缩写展开等）合并成一组，对应前后两个已对齐词
+// 之间的原文。返回按原文顺序排列的片段，以及无法对齐的词和没有被任何词覆盖的原文
+fn align_words(words: &[Cue], all_text: &str) -> (Vec<TextSpan>, Vec<Value>) {
+    let source = normalize_with_positions(all_text);
+    let needles: Vec<Vec<char>> = words
+        .iter()
+        .map(|word| word.content.chars().flat_map(normalize_char).collect())
+        .collect();
+    let mut spans: Vec<TextSpan> = Vec::new();
+    let mut unaligned: Vec<Value> = Vec::new();
+    let mut pending: Vec<&Cue> = Vec::new();
+    let mut cursor: usize = 0;
+    // 上一个片段在原文中的结束位置
+    let mut text_cursor = 0;
+
+    // 把 source[cursor..until] 这段原文交给 pending 中的词，或作为未朗读的原文报告
+    let mut flush = |pending: &mut Vec<&Cue>,
+                     spans: &mut Vec<TextSpan>,
+                     text_cursor: &mut usize,
+                     cursor: usize,
+                     until: usize,
+                     next_start: Option<u64>| {
+        let text = (cursor < until).then(|| (source[cursor].1, source[until - 1].2));
+        if pending.is_empty() {
+            if let Some((text_start, text_end)) = text {
+                let start = spans.last().map_or(0, |span| span.end);
+                unaligned.push(json!({
+                    "text": &all_text[text_start..text_end],
+                    "words": [],
+                    "start": start / TICKS_PER_MS,
+                    "end": next_start.unwrap_or(start) / TICKS_PER_MS,
+                }));
+            }
+            return;
+        }
+
+        let (text_start, text_end) = text.unwrap_or((*text_cursor, *text_cursor));
+        let start = pending[0].start;
+        let end = pending[pending.len() - 1].end;
+        unaligned.push(json!({
+            "text": &all_text[text_start..text_end],
+            "words": pending.iter().map(|word| word.content.as_str()).collect::<Vec<_>>(),
+            "start": start / TICKS_PER_MS,
+            "end": end / TICKS_PER_MS,
+        }));
+        spans.push(TextSpan {
+            start,
+            end,
+            text_start,
+            text_end,
+        });
+        *text_cursor = text_end;
+        pending.clear();
+    };
+
+    for (i, word) in words.iter().enumerate() {
+        let needle = &needles[i];
+        if needle.is_empty() {
+            pending.push(word);
+            continue;
+        }
+
+        let window = needle.len().saturating_mul(4).max(20);
+        let last = source
+            .len()
+            .saturating_sub(needle.len())
+            .min(cursor.saturating_add(window));
+        let position = (cursor..=last)
+            .find(|&p| matches_at(&source, p, needle))
+            .or_else(|| find_anchor(&source, cursor, &needles[i..]));
+
+        match position {
+            Some(p) => {
+                flush(
+                    &mut pending,
+                    &mut spans,
+                    &mut text_cursor,
+                    cursor,
+                    p,
+                    Some(word.start),
+                );
+                let text_start = source[p].1;
+                let text_end = source[p + needle.len() - 1].2;
+                spans.push(TextSpan {
+                    start: word.start,
+                    end: word.end,
+                    text_start,
+                    text_end,
+                });
+                text_cursor = text_end;
+                cursor = p + needle.len();
+            }
+            None => pending.push(word),
+        }
+    }
+    flush(
+        &mut pending,
+        &mut spans,
+        &mut text_cursor,
+        cursor,
+        source.len(),
+        None,
+    );
+
+    (spans, unaligned)
+}
+
+fn matches_at(source: &[(char, usize, usize)], position: usize, needle: &[char]) -> bool {
+    source.len() >= position + needle.len()
+        && source[position..position + needle.len()]
+            .iter()
+            .zip(needle)
+            .all(|((a, _, _), b)| a == b)
+}
+
+// 用 needles[0] 开头的短语在 source[cursor..] 中查找，短语只出现一次时返回其位置
+fn find_anchor(
+    source: &[(char, usize, usize)],
+    cursor: usize,
+    needles: &[Vec<char>],
+) -> Option<usize> {
+    let mut phrase: Vec<char> = Vec::new();
+    for needle in needles.iter().take(ANCHOR_MAX_WORDS) {
+        phrase.extend(needle);
+        if phrase.len() >= ANCHOR_MIN_CHARS {
+            break;
+        }
+    }
+    if phrase.len() < ANCHOR_MIN_CHARS {
+        return None;
+    }
+
+    let mut found = (cursor..source.len()).filter(|&p| matches_at(source, p, &phrase));
+    match (found.next(), found.next()) {
+        (Some(position), None) => Some(position),
+        _ => None,
+    }
+}
+
+// 跳过开头的空白和标点，返回第一个正文字符的位置
+fn content_start(text: &str, is_punctuation: impl Fn(char) -> bool) -> usize {
+    text.char_indices()
+        .find(|(_, c)| !c.is_whitespace() && !is_punctuation(*c))
+        .map_or(text.len(), |(i, _)| i)
+}
+
+// 去掉结尾的空白和标点，返回最后一个正文字符之后的位置
+fn content_end(text: &str, is_punctuation: impl Fn(char) -> bool) -> usize {
+    text.char_indices()
+        .rev()
+        .find(|(_, c)| !c.is_whitespace() && !is_punctuation(*c))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+fn push_text_cue(cues: &mut Vec<Cue>, start: u64, end: u64, text: &str) {
+    let content = text.trim();
+    if content.is_empty() {
+        return;
+    }
+    cues.push(Cue {
+        index: cues.len() + 1,
+        start,
+        end,
+        content: content.to_string(),
+    });
+}
+
+// 中日韩文字（含全角标点），这些文字之间不使用空格分隔
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF     // 谚文字母
+        | 0x2E80..=0x303F   // 部首、中日韩符号和标点
+        | 0x3040..=0x30FF   // 平假名、片假名
+        | 0x3100..=0x31FF   // 注音、谚文兼容字母等
+        | 0x3400..=0x4DBF   // 扩展 A
+        | 0x4E00..=0x9FFF   // 基本汉字
+        | 0xAC00..=0xD7AF   // 谚文音节
+        | 0xF900..=0xFAFF   // 兼容汉字
+        | 0xFF00..=0xFF60   // 全角 ASCII 与标点
+        | 0xFFE0..=0xFFE6   // 全角符号
+        | 0x20000..=0x3FFFF) // 扩展 B 及之后
+}
+
+// SRT 的时间格式 HH:MM:SS,mmm
+fn srt_time(ticks: u64) -> String {
+    let (hours, minutes, seconds, ms) = split_ms(ticks / TICKS_PER_MS);
+    format!("{:02}:{:02}:{:02},{:03}", hours, minutes, seconds, ms)
+}
+
+// WebVTT 的时间格式 HH:MM:SS.mmm
+fn vtt_time(ticks: u64) -> String {
+    let (hours, minutes, seconds, ms) = split_ms(ticks / TICKS_PER_MS);
+    format!("{:02}:{:02}:{:02}.{:03}", hours, minutes, seconds, ms)
+}
+
+// ASS 的时间格式 H:MM:SS.cc，精确到百分之一秒
+fn ass_time(ticks: u64) -> String {
+    let (hours, minutes, seconds, ms) = split_ms(round_to_cs(ticks) * 10);
+    format!("{}:{:02}:{:02}.{:02}", hours, minutes, seconds, ms / 10)
+}
+
+fn round_to_cs(ticks: u64) -> u64 {
+    (ticks + TICKS_PER_CS / 2) / TICKS_PER_CS
+}
+
+// 毫秒拆分成 (时, 分, 秒, 毫秒)
+fn split_ms(milliseconds: u64) -> (u64, u64, u64, u64) {
+    (
+        milliseconds / 3600000,
+        (milliseconds % 3600000) / 60000,
+        (milliseconds % 60000) / 1000,
+        milliseconds % 1000,
+    )
+}
+
+// ASS 的文本中换行写作 \N，花括号会被当作样式标签，
+// 反斜杠后插入零宽空格，避免与 \N、\h 等转义混淆
+fn escape_ass_text(text: &str) -> String {
+    text.replace('\\', "\\\u{200B}")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+        .replace("\r\n", "\\N")
+        .replace('\n', "\\N")
+}
+
+// 把 #RRGGBB / #RRGGBBAA 转成 ASS 的 &HAABBGGRR（ASS 的透明度 00 为不透明）
+fn ass_colour(colour: &str) -> Result<String, CustomResult> {
+    let invalid = || CustomResult::error(Some(format!("无效的颜色：{}", colour)), None);
+    let colour = colour.trim();
+
+    if let Some(hex) = colour
+        .strip_prefix("&H")
+        .or_else(|| colour.strip_prefix("&h"))
+    {
+        let hex = hex.trim_end_matches('&');
+        if hex.is_empty() || hex.len() > 8 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        return Ok(format!("&H{:0>8}", hex.to_uppercase()));
+    }
 
-    fn microseconds_to_time(&self, microseconds: u64) -> String {
-        let milliseconds = (microseconds / 10000) as u64;
-        let hours = milliseconds / 3600000;
-        let minutes = (milliseconds % 3600000) / 60000;
-        let seconds = (milliseconds % 60000) / 1000;
-        let ms = milliseconds % 1000;
-        format!("{:02}:{:02}:{:02},{:03}", hours, minutes, seconds, ms)
+    let hex = colour.strip_prefix('#').ok_or_else(invalid)?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
     }
+    let (rgb, alpha) = match hex.len() {
+        6 => (hex, 255),
+        8 => (
+            &hex[..6],
+            u8::from_str_radix(&hex[6..], 16).map_err(|_| invalid())?,
+        ),
+        _ => return Err(invalid()),
+    };
+    let (r, g, b) = (&rgb[0..2], &rgb[2..4], &rgb[4..6]);
+
+    Ok(format!("&H{:02X}{}{}{}", 255 - alpha, b, g, r).to_uppercase())
 }
 
-// 写入 SRT 文件
+// 写入字幕文件，格式由 options.format 决定
 pub fn generate_srt(
     messages: &[serde_json::Value],
     srt_path: &str,
     option: &str,
     number: i32,
     all_text: Option<&str>,
+    options: &SubtitleOptions,
 ) -> Result<CustomResult, CustomResult> {
+    let (mut maker, merge_result) = merge_messages(messages, option, number, all_text, options)?;
+    write_subtitle(&mut maker, srt_path, options)?;
+    Ok(merge_result)
+}
+
+// 读取语音服务返回的边界信息并按 option 分句，返回分句结果（标点分句时包含无法对齐的片段）
+pub fn merge_messages(
+    messages: &[serde_json::Value],
+    option: &str,
+    number: i32,
+    all_text: Option<&str>,
+    options: &SubtitleOptions,
+) -> Result<(SubMaker, CustomResult), CustomResult> {
     let mut maker = SubMaker::new();
 
     for msg in messages {
-        if let Some(meta) = msg.get("Metadata").and_then(|v| v.get(0)) {
-            if meta["Type"] == "WordBoundary" {
-                let _ = maker.feed(meta)?;
+        if let Some(metadata) = msg.get("Metadata").and_then(|v| v.as_array()) {
+            for meta in metadata {
+                if meta["Type"] == "WordBoundary" || meta["Type"] == "SentenceBoundary" {
+                    let _ = maker.feed(meta)?;
+                }
             }
         }
     }
 
+    // 标点分句会返回无法与原文对齐的片段
+    let mut merge_result = None;
     match option {
-        "mergeByPunctuation" => {
-            let _ = maker.merge_by_punctuation(all_text.unwrap_or(""))?;
+        "mergeBySentence" if maker.has_sentences() => {
+            let _ = maker.merge_by_sentence()?;
+        }
+        // 语音服务没有返回句子边界时，退回到标点分句
+        "mergeBySentence" | "mergeByPunctuation" => {
+            merge_result = Some(maker.merge_by_punctuation(all_text.unwrap_or(""))?);
         }
         "mergeByNumber" => {
             let _ = maker.merge_by_number(number)?;
         }
+        "mergeByConstraints" => {
+            let _ = maker.merge_by_constraints(&options.merge_constraints)?;
+        }
+        "mergeByPause" => {
+            let threshold = options.pause_threshold_ms.unwrap_or(DEFAULT_PAUSE_MS);
+            let _ = maker.merge_by_pause(threshold)?;
+        }
         _ => {}
     }
 
-    let srt_content = maker.get_srt();
+    // 其他分句方式也可以叠加按停顿拆分
+    if let Some(threshold) = options.pause_threshold_ms {
+        if option != "mergeByPause" {
+            maker.split_by_pause(threshold);
+        }
+    }
+
+    let merge_result = merge_result.unwrap_or_else(|| CustomResult::success(None, None));
+    Ok((maker, merge_result))
+}
+
+// 分句之后的时间调整、换行和输出，导入的字幕也使用这一步
+pub fn write_subtitle(
+    maker: &mut SubMaker,
+    srt_path: &str,
+    options: &SubtitleOptions,
+) -> Result<(), CustomResult> {
+    if !options.cue_timing.is_empty() {
+        maker.adjust_timing(&options.cue_timing);
+    }
+
+    if options.time_offset_ms != 0 || options.time_scale != 1.0 {
+        let _ = maker.retime(options.time_offset_ms, options.time_scale)?;
+    }
+
+    // LRC 每句只能有一行，不做换行
+    if let Some(width) = options.line_width {
+        if options.format != SubtitleFormat::Lrc {
+            maker.wrap_lines(width);
+        }
+    }
+
+    let srt_content = match options.format {
+        SubtitleFormat::Srt => maker.get_srt(),
+        SubtitleFormat::Vtt => maker.get_vtt(options.vtt_cue_settings.as_deref()),
+        SubtitleFormat::Ass => maker.get_ass(&options.ass_style, options.ass_karaoke)?,
+        SubtitleFormat::Lrc => maker.get_lrc(options.lrc_enhanced),
+    };
     let mut file =
         File::create(srt_path).map_err(|e| CustomResult::error(Some(e.to_string()), None))?;
     file.write_all(srt_content.as_bytes())
         .map_err(|e| CustomResult::error(Some(e.to_string()), None))?;
-    Ok(CustomResult::success(None, None))
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 时间单位为毫秒
+    fn word(start: u64, end: u64, text: &str) -> Cue {
+        Cue {
+            index: 0,
+            start: start * 10_000,
+            end: end * 10_000,
+            content: text.to_string(),
+        }
+    }
+
+    fn maker_with_words(words: &[(u64, u64, &str)]) -> SubMaker {
+        let words: Vec<Cue> = words
+            .iter()
+            .map(|(start, end, text)| word(*start, *end, text))
+            .collect();
+        SubMaker {
+            cues: words.clone(),
+            words,
+            sentences: Vec::new(),
+        }
+    }
+
+    fn syllable_texts(maker: &SubMaker, cue: &Cue) -> Vec<String> {
+        maker
+            .cue_syllables(cue)
+            .into_iter()
+            .map(|(_, _, text)| text)
+            .collect()
+    }
+
+    #[test]
+    fn feed_rejects_incomplete_metadata() {
+        let mut maker = SubMaker::new();
+        let sentence = json!({
+            "Type": "SentenceBoundary",
+            "Data": { "Offset": 1_000_000, "Duration": 5_000_000, "text": { "Text": "Hello there." } }
+        });
+        assert!(maker.feed(&sentence).is_ok());
+        assert_eq!(maker.sentences.len(), 1);
+        assert_eq!(maker.sentences[0].end, 6_000_000);
+
+        let missing_offset = json!({
+            "Type": "WordBoundary",
+            "Data": { "Duration": 5_000_000, "text": { "Text": "Hello" } }
+        });
+        assert!(maker.feed(&missing_offset).is_err());
+        let missing_text = json!({
+            "Type": "SentenceBoundary",
+            "Data": { "Offset": 0, "Duration": 5_000_000, "text": {} }
+        });
+        assert!(maker.feed(&missing_text).is_err());
+        assert!(maker.words.is_empty());
+        assert_eq!(maker.sentences.len(), 1);
+    }
+
+    #[test]
+    fn syllables_keep_source_text_for_unmatched_words() {
+        let maker = maker_with_words(&[
+            (0, 100, "It"),
+            (100, 400, "costs"),
+            (400, 700, "five"),
+            (700, 1000, "dollars"),
+        ]);
+        let cue = word(0, 1000, "It costs $5.");
+
+        assert_eq!(
+            syllable_texts(&maker, &cue),
+            vec!["It", " costs", " $5.", ""]
+        );
+    }
+
+    #[test]
+    fn syllables_share_gap_between_matched_words() {
+        let maker = maker_with_words(&[
+            (0, 100, "Pay"),
+            (100, 200, "five"),
+            (200, 300, "dollars"),
+            (300, 400, "now"),
+        ]);
+        let cue = word(0, 400, "Pay $5 USD now!");
+
+        let texts = syllable_texts(&maker, &cue);
+        assert_eq!(texts, vec!["Pay", " $5", " USD ", "now!"]);
+        assert_eq!(texts.concat(), cue.content);
+    }
+
+    #[test]
+    fn karaoke_uses_source_text() {
+        let mut maker = maker_with_words(&[
+            (0, 100, "It"),
+            (100, 400, "costs"),
+            (400, 700, "five"),
+            (700, 1000, "dollars"),
+        ]);
+        maker.cues = vec![word(0, 1000, "It costs $5.")];
+
+        let ass = maker
+            .get_ass(&AssStyle::default(), true)
+            .expect("get_ass failed");
+        assert!(ass.contains(" $5."));
+        assert!(!ass.contains("five"));
+    }
+
+    fn cue_seconds(cue: &Cue) -> f64 {
+        (cue.end - cue.start) as f64 / 10_000_000.0
+    }
+
+    #[test]
+    fn constraints_limit_reading_speed() {
+        // 每个词单独显示正好 8 字/秒，两个词合并后为 9 字/秒
+        let mut words: Vec<(u64, u64, &str)> =
+            (0..8).map(|i| (i * 500, i * 500 + 500, "word")).collect();
+        // 短词合并后读得更慢，可以带上前面的长词
+        words.extend((8..16).map(|i| (i * 500, i * 500 + 500, "a")));
+        let mut maker = maker_with_words(&words);
+        let constraints = MergeConstraints {
+            max_chars_per_second: 8.0,
+            ..MergeConstraints::default()
+        };
+        maker
+            .merge_by_constraints(&constraints)
+            .expect("merge_by_constraints failed");
+
+        let texts: Vec<&str> = maker.cues.iter().map(|cue| cue.content.as_str()).collect();
+        assert_eq!(&texts[..6], &["word"; 6]);
+        assert_eq!(texts[6], "word word a a a a a a a a");
+        for cue in maker.cues.iter() {
+            let chars = display_width(cue.content.trim()) as f64;
+            assert!(
+                chars / cue_seconds(cue) <= constraints.max_chars_per_second,
+                "{:?} is read too fast",
+                cue.content
+            );
+        }
+        // 最后一条只按最短显示时间延长，不再为了阅读速度拉长到最长显示时间
+        assert!(maker.cues.last().map_or(0, |cue| cue.end) <= 8_000 * 10_000);
+    }
+
+    #[test]
+    fn constraints_limit_line_width_and_duration() {
+        let words: Vec<(u64, u64, &str)> =
+            (0..12).map(|i| (i * 300, i * 300 + 300, "abcd")).collect();
+        let mut maker = maker_with_words(&words);
+        let constraints = MergeConstraints {
+            max_chars_per_line: 10,
+            max_lines: 1,
+            max_duration_ms: 2000,
+            ..MergeConstraints::default()
+        };
+        maker
+            .merge_by_constraints(&constraints)
+            .expect("merge_by_constraints failed");
+
+        for cue in maker.cues.iter() {
+            assert!(display_width(&cue.content) <= 10, "{:?}", cue.content);
+            assert!(cue_seconds(cue) <= 2.0);
+        }
+        assert_eq!(maker.cues.len(), 6);
+        let all: Vec<&str> = maker
+            .cues
+            .iter()
+            .flat_map(|cue| cue.content.split_whitespace())
+            .collect();
+        assert_eq!(all, vec!["abcd"; 12]);
+    }
+
+    #[test]
+    fn constraints_check_line_breaks() {
+        let constraints = MergeConstraints {
+            max_chars_per_line: 10,
+            max_lines: 2,
+            ..MergeConstraints::default()
+        };
+        // 总宽度不超过 20，但按词断行需要三行
+        assert!(!constraints.fits_lines("abcdef abcdef abcdef"));
+        assert!(constraints.fits_lines("abcd abcd abcd abcd"));
+        assert!(constraints.fits_lines("中文字幕测试中文字幕"));
+        assert!(!constraints.fits_lines("中文字幕测试中文字幕测试"));
+    }
+
+    fn spaced_words(text: &str) -> Vec<(u64, u64, &str)> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(i, word)| (i as u64 * 300, i as u64 * 300 + 250, word))
+            .collect()
+    }
+
+    fn aligned_texts<'a>(words: &[Cue], text: &'a str) -> Vec<&'a str> {
+        let (spans, _) = align_words(words, text);
+        spans
+            .iter()
+            .map(|span| &text[span.text_start..span.text_end])
+            .collect()
+    }
+
+    #[test]
+    fn align_expanded_digits() {
+        let text = "I have 3 cats and 20 dogs.";
+        let maker = maker_with_words(&spaced_words("I have three cats and twenty dogs"));
+
+        assert_eq!(
+            aligned_texts(&maker.words, text),
+            vec!["I", "have", "3", "cats", "and", "20", "dogs"]
+        );
+    }
+
+    #[test]
+    fn align_expanded_abbreviations() {
+        let text = "Dr. Smith lives on Baker St. now.";
+        let maker = maker_with_words(&spaced_words("Doctor Smith lives on Baker Street now"));
+        let (_, unaligned) = align_words(&maker.words, text);
+
+        assert_eq!(
+            aligned_texts(&maker.words, text),
+            vec!["Dr", "Smith", "lives", "on", "Baker", "St", "now"]
+        );
+        assert_eq!(unaligned.len(), 2);
+        assert_eq!(unaligned[0]["text"], "Dr");
+        assert_eq!(unaligned[0]["words"], json!(["Doctor"]));
+        assert_eq!(unaligned[1]["text"], "St");
+    }
+
+    #[test]
+    fn align_resyncs_after_unread_span() {
+        let text = "See https://example.com/some/very/long/path/here for details. Then we go home.";
+        let mut maker = maker_with_words(&spaced_words("See link for details Then we go home"));
+        let result = maker
+            .merge_by_punctuation(text)
+            .expect("merge_by_punctuation failed");
+
+        assert_eq!(
+            maker.cue_texts(),
+            vec![
+                "See https://example.com/some/very/long/path/here for details",
+                "Then we go home"
+            ]
+        );
+        let unaligned = result.data["unaligned"]
+            .as_array()
+            .expect("missing unaligned report");
+        assert_eq!(unaligned.len(), 1);
+        assert_eq!(unaligned[0]["words"], json!(["link"]));
+        assert_eq!(
+            unaligned[0]["text"],
+            "https://example.com/some/very/long/path/here"
+        );
+        assert_eq!(
+            spoken_chars(&maker.cue_texts().concat()),
+            spoken_chars(text)
+        );
+    }
+
+    // 去掉标点和空白后的文本，用于检查分句没有丢失原文
+    fn spoken_chars(text: &str) -> String {
+        text.chars().filter(|c| c.is_alphanumeric()).collect()
+    }
+
+    #[test]
+    fn punctuation_splits_at_source_punctuation() {
+        let text = "“你好，世界！”今天天气不错。Hello, world... OK?";
+        let mut maker = maker_with_words(&spaced_words("你好 世界 今天天气不错 Hello world OK"));
+        let result = maker
+            .merge_by_punctuation(text)
+            .expect("merge_by_punctuation failed");
+
+        assert_eq!(
+            maker.cue_texts(),
+            vec!["你好", "世界", "今天天气不错", "Hello", "world", "OK"]
+        );
+        assert_eq!(result.data["unaligned"], json!([]));
+        assert_eq!(
+            spoken_chars(&maker.cue_texts().concat()),
+            spoken_chars(text)
+        );
+        assert!(maker.merge_by_punctuation("").is_err());
+    }
+
+    #[test]
+    fn align_reports_unread_text() {
+        let text = "One two. This sentence was never read aloud by the voice. Three four.";
+        let maker = maker_with_words(&spaced_words("One two Three four"));
+        let (spans, unaligned) = align_words(&maker.words, text);
+
+        assert_eq!(spans.len(), 4);
+        assert_eq!(unaligned.len(), 1);
+        assert_eq!(unaligned[0]["words"], json!([]));
+        assert_eq!(
+            unaligned[0]["text"],
+            "This sentence was never read aloud by the voice"
+        );
+    }
+
+    fn parsed(content: &str) -> (Vec<String>, Vec<(u64, u64)>) {
+        match SubMaker::from_subtitle(content) {
+            Ok(maker) => (maker.cue_texts(), maker.cue_times()),
+            Err(e) => panic!("{}", e.msg),
+        }
+    }
+
+    fn ms(start: u64, end: u64) -> (u64, u64) {
+        (start * TICKS_PER_MS, end * TICKS_PER_MS)
+    }
+
+    #[test]
+    fn subtitle_whitespace_only_separator() {
+        let (texts, times) = parsed(
+            "1\n00:00:01,000 --> 00:00:02,000\nHello\n \t\n2\n00:00:03,000 --> 00:00:04,000\nWorld\n",
+        );
+        assert_eq!(texts, vec!["Hello", "World"]);
+        assert_eq!(times, vec![ms(1000, 2000), ms(3000, 4000)]);
+    }
+
+    #[test]
+    fn subtitle_crlf_and_bom() {
+        let (texts, times) = parsed(
+            "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i>\r\nthere\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\nWorld\r\n",
+        );
+        assert_eq!(texts, vec!["Hello there", "World"]);
+        assert_eq!(times, vec![ms(1000, 2500), ms(3000, 4000)]);
+    }
+
+    #[test]
+    fn subtitle_missing_blank_line() {
+        let (texts, _) = parsed(
+            "1\n00:00:01,000 --> 00:00:02,000\nHello\n2\n00:00:03,000 --> 00:00:04,000\nWorld",
+        );
+        assert_eq!(texts, vec!["Hello", "World"]);
+    }
+
+    #[test]
+    fn subtitle_vtt_blocks_and_short_timestamps() {
+        let (texts, times) = parsed(
+            "WEBVTT - title\nKind: captions\n\nSTYLE\n::cue { color: yellow }\n\nNOTE this is\na comment\n\nREGION\nid:top\n\nintro\n00:01.000 --> 00:02.500 align:center line:90%\n<c.yellow>Hello</c> &amp; welcome\n\nNOTE\nanother\n\n01:02.000 --> 01:03.000\n<v Bob>World",
+        );
+        assert_eq!(texts, vec!["Hello & welcome", "World"]);
+        assert_eq!(times, vec![ms(1000, 2500), ms(62_000, 63_000)]);
+    }
+
+    #[test]
+    fn subtitle_rejects_bad_timing() {
+        assert!(SubMaker::from_subtitle("1\n00:00:01,000 --> 00:00:xx\nHello").is_err());
+        assert!(SubMaker::from_subtitle("1\nHello").is_err());
+    }
+
+    #[test]
+    fn subtitle_keeps_unclosed_angle_bracket() {
+        let (texts, _) = parsed(
+            "1\n00:00:01,000 --> 00:00:02,000\n5 < 6 apples\n<i>and</i> {\\an8}3 > 2 {not a tag",
+        );
+        assert_eq!(texts, vec!["5 < 6 apples and 3 > 2 {not a tag"]);
+    }
+
+    #[test]
+    fn subtitle_arrow_in_text() {
+        let (texts, times) = parsed(
+            "1\n00:00:01,000 --> 00:00:02,000\nA --> B\n2\n00:00:03,000 --> 00:00:04,000\nC",
+        );
+        assert_eq!(texts, vec!["A --> B", "C"]);
+        assert_eq!(times, vec![ms(1000, 2000), ms(3000, 4000)]);
+    }
+
+    #[test]
+    fn subtitle_retimed_to_synthesized_audio() {
+        let mut maker = SubMaker::from_subtitle(
+            "1\n00:00:01,000 --> 00:00:02,000\nHello\n\n2\n00:00:03,000 --> 00:00:04,000\nWorld",
+        )
+        .expect("from_subtitle failed");
+        assert!(maker.set_cue_times(&[ms(0, 1500)]).is_err());
+
+        maker
+            .set_cue_times(&[ms(0, 1500), ms(1500, 2250)])
+            .expect("set_cue_times failed");
+        assert_eq!(maker.cue_times(), vec![ms(0, 1500), ms(1500, 2250)]);
+        let srt = maker.get_srt();
+        assert!(srt.contains("00:00:00,000 --> 00:00:01,500\nHello"));
+        assert!(srt.contains("00:00:01,500 --> 00:00:02,250\nWorld"));
+    }
 }
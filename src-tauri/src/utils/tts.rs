@@ -1,6 +1,7 @@
 //! 本文件内代码参考开源项目：https://github.com/rany2/edge-tts/tree/master
 
 use crate::utils::custom_result::CustomResult;
+use crate::utils::ssml::{escaped_char_len, escaped_len, SsmlBuilder};
 use chrono::Utc;
 use hex;
 use serde_json::json;
@@ -8,6 +9,14 @@ use sha2::{Digest, Sha256};
 
 pub struct TTS {}
 
+// 单次请求允许的最大文本字节数，超出的文本会被拆分成多段分别合成
+pub const MAX_CHUNK_BYTES: usize = 4096;
+
+// 句末标点，优先在这些位置拆分
+const SENTENCE_END_CHARS: &[char] = &['。', '！', '？', '!', '?', '.', '；', ';', '…', '\n'];
+// 句中停顿标点，找不到句末标点时在这些位置拆分
+const CLAUSE_END_CHARS: &[char] = &['，', ',', '、', '：', ':'];
+
 impl TTS {
     fn num_to_str(&self, num: &str) -> String {
         if !num.contains('e') {
@@ -29,43 +38,99 @@ impl TTS {
 
     pub fn convert_to_audio_format_websocket_string(&self, output_format: &str) -> String {
         format!(
-            "X-Timestamp:{}\r\nContent-Type:application/json; charset=utf-8\r\nPath:speech.config\r\n\r\n{{\"context\":{{\"synthesis\":{{\"audio\":{{\"metadataoptions\":{{\"sentenceBoundaryEnabled\":\"false\",\"wordBoundaryEnabled\":\"true\"}},\"outputFormat\":\"{}\"}}}}}}}}",
+            "X-Timestamp:{}\r\nContent-Type:application/json; charset=utf-8\r\nPath:speech.config\r\n\r\n{{\"context\":{{\"synthesis\":{{\"audio\":{{\"metadataoptions\":{{\"sentenceBoundaryEnabled\":\"true\",\"wordBoundaryEnabled\":\"true\"}},\"outputFormat\":\"{}\"}}}}}}}}",
             self.date_to_string(),
             output_format
         )
     }
 
-    fn convert_to_ssml_text(
+    pub fn convert_to_ssml_text(
         &self,
         voice: &str,
         text: &str,
-        pitch: &str,
-        rate: &str,
-        volume: &str,
-    ) -> String {
-        format!(
-            "<speak version='1.0' xmlns='http://www.w3.org/2001/10/synthesis' xml:lang='en-US'><voice name='{}'><prosody pitch='{}Hz' rate ='{}%' volume='{}%'>{}</prosody></voice></speak>",
-            voice, pitch, rate, volume, text
-        )
+        pitch: i32,
+        rate: i32,
+        volume: i32,
+    ) -> Result<String, CustomResult> {
+        let ssml = SsmlBuilder::new(voice)
+            .pitch(pitch)
+            .rate(rate)
+            .volume(volume)
+            .build(text)?;
+        Ok(ssml)
     }
 
-    pub fn convert_to_ssml_websocket_string(
-        &self,
-        request_id: &str,
-        voice: &str,
-        msg: &str,
-        pitch: &str,
-        rate: &str,
-        volume: &str,
-    ) -> String {
+    pub fn convert_to_ssml_websocket_string(&self, request_id: &str, ssml: &str) -> String {
         format!(
             "X-RequestId:{}\r\nContent-Type:application/ssml+xml\r\nX-Timestamp:{}Z\r\nPath:ssml\r\n\r\n{}",
             request_id,
             self.date_to_string(),
-            self.convert_to_ssml_text(voice, msg, pitch, rate, volume)
+            ssml
         )
     }
 
+    // 按转义后的字节长度拆分文本，尽量保证每段都在句子边界结束
+    pub fn split_text_by_byte_length(&self, text: &str, max_bytes: usize) -> Vec<String> {
+        let mut chunks = Vec::new();
+        let mut rest = text.trim();
+
+        while escaped_len(rest) > max_bytes {
+            let split_at = self.find_split_position(rest, max_bytes);
+            let (chunk, remain) = rest.split_at(split_at);
+            let chunk = chunk.trim();
+            if !chunk.is_empty() {
+                chunks.push(chunk.to_string());
+            }
+            rest = remain.trim_start();
+        }
+
+        if !rest.is_empty() {
+            chunks.push(rest.to_string());
+        }
+
+        chunks
+    }
+
+    // 在 max_bytes 范围内寻找最合适的拆分位置（返回拆分处的字节下标）
+    fn find_split_position(&self, text: &str, max_bytes: usize) -> usize {
+        // 按转义后的长度计算窗口，且不能把一个 UTF-8 字符拆成两半
+        let mut limit = 0;
+        let mut size = 0;
+        for (i, c) in text.char_indices() {
+            size += escaped_char_len(c);
+            if size > max_bytes {
+                break;
+            }
+            limit = i + c.len_utf8();
+        }
+        let window = &text[..limit];
+
+        let last_after = |is_boundary: &dyn Fn(char) -> bool| {
+            window
+                .char_indices()
+                .rev()
+                .find(|(_, c)| is_boundary(*c))
+                .map(|(i, c)| i + c.len_utf8())
+        };
+
+        if let Some(pos) = last_after(&|c| SENTENCE_END_CHARS.contains(&c)) {
+            return pos;
+        }
+        if let Some(pos) = last_after(&|c| CLAUSE_END_CHARS.contains(&c)) {
+            return pos;
+        }
+        if let Some(pos) = last_after(&|c| c.is_whitespace()) {
+            return pos;
+        }
+
+        // 没有任何可用的边界，只能按字符硬拆
+        if limit == 0 {
+            text.chars().next().map(|c| c.len_utf8()).unwrap_or(text.len())
+        } else {
+            limit
+        }
+    }
+
     fn date_to_string(&self) -> String {
         let now = Utc::now();
         now.format("%a %b %d %Y %H:%M:%S GMT+0000 (Coordinated Universal Time)")
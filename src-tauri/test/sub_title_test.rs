@@ -1,6 +1,8 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use utils::sub_marker::generate_srt;
+use utils::sub_marker::{
+    generate_srt, AssStyle, CueTiming, MergeConstraints, SubtitleFormat, SubtitleOptions,
+};
 pub mod utils;
 
 use std::fs;
@@ -16,7 +18,20 @@ fn main() -> io::Result<()> {
     let txt_path = "test/复杂文本2.txt";
     let txt_content = fs::read_to_string(txt_path)?;
     
-    let result = generate_srt(&messages, "test/output.srt", "mergeByNumber", 5, Some(txt_content.as_str()));
+    let options = SubtitleOptions {
+        format: SubtitleFormat::Srt,
+        vtt_cue_settings: None,
+        ass_style: AssStyle::default(),
+        ass_karaoke: false,
+        lrc_enhanced: false,
+        merge_constraints: MergeConstraints::default(),
+        line_width: None,
+        pause_threshold_ms: None,
+        cue_timing: CueTiming::default(),
+        time_offset_ms: 0,
+        time_scale: 1.0,
+    };
+    let result = generate_srt(&messages, "test/output.srt", "mergeByNumber", 5, Some(txt_content.as_str()), &options);
 
     match result {
         Ok(_) => print!("成功"),